@@ -1,3 +1,18 @@
+pub mod analysis;
+pub mod assembler;
+pub mod audio;
+pub mod breakpoint;
 pub mod constants;
+pub mod dap;
+pub mod debugger;
 pub mod decompiler;
 pub mod emulator;
+pub mod frontend;
+pub mod gdb;
+pub mod graph;
+pub mod instruction;
+pub mod quirks;
+pub mod rewind;
+pub mod scheduler;
+#[cfg(feature = "sdl")]
+pub mod sdl;
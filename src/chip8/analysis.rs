@@ -0,0 +1,232 @@
+use super::constants::RAM_OFFSET;
+use super::instruction::{decode_at, size_at, Instruction};
+
+use std::collections::HashSet;
+
+// how execution can continue after an instruction
+#[derive(Debug, PartialEq)]
+pub enum Flow {
+    Next,
+    Jump(u16),
+    Call(u16),
+    // skip instructions continue at either the next instruction or the one after it
+    Skip,
+    Return,
+    // somewhere we can't follow statically, like JP V0 or an invalid instruction, or EXIT
+    Stop,
+}
+
+// the ways an instruction can refer to an address
+#[derive(Debug, PartialEq, Clone, Copy, PartialOrd, Eq, Ord)]
+pub enum Reference {
+    Load,
+    Table,
+    Jump,
+    Call,
+}
+
+// `code` starts with the instruction, as in every function here that takes it
+pub fn reference(code: &[u8]) -> Option<(Reference, u16)> {
+    match decode_at(code) {
+        Ok(Instruction::Jp(address)) => Some((Reference::Jump, address)),
+        Ok(Instruction::Call(address)) => Some((Reference::Call, address)),
+        Ok(Instruction::LdI(address)) | Ok(Instruction::LdILong(address)) => {
+            Some((Reference::Load, address))
+        }
+        Ok(Instruction::JpV0(address)) => Some((Reference::Table, address)),
+        _ => None,
+    }
+}
+
+pub fn flow(code: &[u8]) -> Flow {
+    use Instruction::*;
+
+    let instruction = match decode_at(code) {
+        Ok(instruction) => instruction,
+        Err(_) => return Flow::Stop,
+    };
+    match instruction {
+        Ret => Flow::Return,
+        Jp(address) => Flow::Jump(address),
+        Call(address) => Flow::Call(address),
+        SeByte(..) | SneByte(..) | SeReg(..) | SneReg(..) | Skp(_) | Sknp(_) => Flow::Skip,
+        JpV0(_) | Exit => Flow::Stop,
+        _ => Flow::Next,
+    }
+}
+
+// where the instruction at `address` carries on to, and where it goes when it skips: past the
+// instruction after it, which can be a 4 byte LD I, LONG
+pub fn successors(code: &[u8], address: u16) -> (u16, u16) {
+    let size = size_at(code);
+    let next = address.wrapping_add(size as u16);
+    let skipped = size_at(code.get(size..).unwrap_or(&[]));
+    (next, next.wrapping_add(skipped as u16))
+}
+
+pub struct Analysis {
+    // for each byte of the ROM, whether a reachable instruction starts there
+    pub code: Vec<bool>,
+    // for each byte of the ROM, whether it is drawn as part of a sprite
+    pub sprite: Vec<bool>,
+}
+
+// walk every path through the ROM from its entry point, keeping track of I where it's known
+// so that sprites can be found from the DRW instructions that use them
+pub fn analyse(rom: &[u8]) -> Analysis {
+    let mut analysis = Analysis {
+        code: vec![false; rom.len()],
+        sprite: vec![false; rom.len()],
+    };
+    let mut visited = HashSet::new();
+    let mut pending: Vec<(u16, Option<u16>)> = vec![(RAM_OFFSET, None)];
+
+    while let Some((address, i)) = pending.pop() {
+        let offset = match address.checked_sub(RAM_OFFSET) {
+            Some(offset) => offset as usize,
+            None => continue,
+        };
+        if offset + 1 >= rom.len() || !visited.insert((address, i)) {
+            continue;
+        }
+        let code = &rom[offset..];
+        let instruction = match decode_at(code) {
+            Ok(instruction) => instruction,
+            Err(_) => continue,
+        };
+        analysis.code[offset] = true;
+
+        let i = match instruction {
+            Instruction::LdI(address) | Instruction::LdILong(address) => Some(address),
+            // only the first plane's worth of a sprite is counted, as the planes aren't tracked
+            Instruction::Drw(_, _, n) => {
+                if let Some(sprite) = i.and_then(|i| i.checked_sub(RAM_OFFSET)) {
+                    let start = (sprite as usize).min(rom.len());
+                    // DRW with n = 0 draws a 16x16 sprite, two bytes to a row
+                    let size = if n == 0 { 32 } else { n as usize };
+                    let end = (start + size).min(rom.len());
+                    analysis.sprite[start..end].fill(true);
+                }
+                i
+            }
+            // ADD I, LD F, LD HF and the register loads and stores all change I
+            Instruction::AddI(_)
+            | Instruction::LdF(_)
+            | Instruction::LdHf(_)
+            | Instruction::LdStore(_)
+            | Instruction::LdRead(_) => None,
+            _ => i,
+        };
+
+        let (next, skip) = successors(code, address);
+        match flow(code) {
+            Flow::Next => pending.push((next, i)),
+            Flow::Jump(target) => pending.push((target, i)),
+            Flow::Call(target) => {
+                // the subroutine may well change I before it returns
+                pending.push((next, None));
+                pending.push((target, i));
+            }
+            Flow::Skip => {
+                pending.push((next, i));
+                pending.push((skip, i));
+            }
+            Flow::Return | Flow::Stop => (),
+        }
+    }
+
+    analysis
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flow() {
+        assert_eq!(flow(&[0x00, 0xe0]), Flow::Next);
+        assert_eq!(flow(&[0x00, 0xee]), Flow::Return);
+        assert_eq!(flow(&[0x12, 0x34]), Flow::Jump(0x234));
+        assert_eq!(flow(&[0x23, 0x45]), Flow::Call(0x345));
+        assert_eq!(flow(&[0x3a, 0x01]), Flow::Skip);
+        assert_eq!(flow(&[0xe1, 0xa1]), Flow::Skip);
+        assert_eq!(flow(&[0xb2, 0x00]), Flow::Stop);
+        assert_eq!(flow(&[0x01, 0x23]), Flow::Stop);
+        assert_eq!(flow(&[0xa3, 0x00]), Flow::Next);
+        assert_eq!(flow(&[0x00, 0xfd]), Flow::Stop);
+        assert_eq!(flow(&[0x00, 0xc2]), Flow::Next);
+    }
+
+    #[test]
+    fn test_reference() {
+        assert_eq!(reference(&[0x12, 0x34]), Some((Reference::Jump, 0x234)));
+        assert_eq!(reference(&[0x23, 0x45]), Some((Reference::Call, 0x345)));
+        assert_eq!(reference(&[0xa3, 0x00]), Some((Reference::Load, 0x300)));
+        assert_eq!(reference(&[0xb2, 0x10]), Some((Reference::Table, 0x210)));
+        assert_eq!(reference(&[0x60, 0x12]), None);
+    }
+
+    #[test]
+    fn test_analyse() {
+        let rom = [
+            0xa2, 0x0a, // 0x200 LD   I,   0x020a
+            0x22, 0x08, // 0x202 CALL 0x0208
+            0x12, 0x04, // 0x204 JP   0x0204
+            0xff, 0xff, // 0x206 unreachable
+            0x00, 0xee, // 0x208 RET
+            0x3c, 0x3c, // 0x20a sprite data that decodes as SE   vC,  0x3c
+        ];
+        let code = analyse(&rom).code;
+        let starts: Vec<usize> = (0..rom.len()).filter(|&i| code[i]).collect();
+        assert_eq!(starts, vec![0, 2, 4, 8]);
+    }
+
+    #[test]
+    fn test_analyse_skips() {
+        let rom = [
+            0x30, 0x00, // 0x200 SE   v0,  0x00
+            0x12, 0x08, // 0x202 JP   0x0208
+            0x00, 0xe0, // 0x204 CLS
+            0x00, 0xee, // 0x206 RET
+            0x00, 0xee, // 0x208 RET
+        ];
+        let code = analyse(&rom).code;
+        assert_eq!(
+            code,
+            vec![true, false, true, false, true, false, true, false, true, false]
+        );
+    }
+
+    #[test]
+    fn test_analyse_long() {
+        let rom = [
+            0x30, 0x00, // 0x200 SE   v0,  0x00
+            0xf0, 0x00, 0x02, 0x0c, // 0x202 LD   I,   LONG 0x020c
+            0xd0, 0x11, // 0x206 DRW  v0,  v1, 0x1
+            0x12, 0x08, // 0x208 JP   0x0208
+            0x00, 0x00, // 0x20a unreachable
+            0x3c, // 0x20c sprite
+        ];
+        let analysis = analyse(&rom);
+        let starts: Vec<usize> = (0..rom.len()).filter(|&i| analysis.code[i]).collect();
+        assert_eq!(starts, vec![0, 2, 6, 8]);
+        assert!(analysis.sprite[12]);
+        assert_eq!(successors(&rom, 0x200), (0x202, 0x206));
+    }
+
+    #[test]
+    fn test_analyse_sprites() {
+        let rom = [
+            0xa2, 0x0c, // 0x200 LD   I,   0x020c
+            0x22, 0x08, // 0x202 CALL 0x0208
+            0xd0, 0x12, // 0x204 DRW  v0,  v1, 0x2 (I is unknown after the call)
+            0x12, 0x06, // 0x206 JP   0x0206
+            0xd0, 0x13, // 0x208 DRW  v0,  v1, 0x3
+            0x00, 0xee, // 0x20a RET
+            0xf0, 0x90, 0xf0, 0x90, // 0x20c sprite
+        ];
+        let sprite = analyse(&rom).sprite;
+        let sprites: Vec<usize> = (0..rom.len()).filter(|&i| sprite[i]).collect();
+        assert_eq!(sprites, vec![12, 13, 14]);
+    }
+}
@@ -0,0 +1,918 @@
+mod preprocessor;
+
+use super::constants::RAM_OFFSET;
+use super::instruction::{self, Instruction};
+use preprocessor::{preprocess, SourceLine};
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+
+#[derive(Debug, PartialEq)]
+pub struct AssemblerError {
+    pub file: Option<String>,
+    // 0 for an error with the file as a whole, such as it not being readable
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AssemblerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.file {
+            Some(file) if self.line == 0 => write!(f, "{}: {}", file, self.message),
+            Some(file) => write!(f, "{}:{}: {}", file, self.line, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+const MNEMONICS: [&str; 29] = [
+    "CLS", "RET", "SYS", "JP", "CALL", "SE", "SNE", "LD", "ADD", "OR", "AND", "XOR", "SUB", "SHR",
+    "SUBN", "SHL", "RND", "DRW", "SKP", "SKNP", "SCD", "SCR", "SCL", "EXIT", "LOW", "HIGH", "SCU",
+    "PLANE", "AUDIO",
+];
+
+// where the code for each statement came from, in order of address, for debuggers
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SourceMap {
+    pub statements: Vec<Mapping>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mapping {
+    pub address: u16,
+    // in bytes
+    pub size: usize,
+    // the path the line is in, or None for source that wasn't read from a file
+    pub file: Option<String>,
+    pub line: usize,
+}
+
+impl SourceMap {
+    // the statement whose code includes the address
+    pub fn at_address(&self, address: u16) -> Option<&Mapping> {
+        self.statements.iter().find(|mapping| {
+            (mapping.address as usize..mapping.address as usize + mapping.size)
+                .contains(&(address as usize))
+        })
+    }
+
+    // the first statement on or after a line, in a file that `in_file` is true for. A line that
+    // has no code, such as a comment or a label, moves to the next that does
+    pub fn at_line<P: FnMut(Option<&str>) -> bool>(
+        &self,
+        line: usize,
+        mut in_file: P,
+    ) -> Option<&Mapping> {
+        self.statements
+            .iter()
+            .filter(|mapping| mapping.line >= line && in_file(mapping.file.as_deref()))
+            .min_by_key(|mapping| (mapping.line, mapping.address))
+    }
+}
+
+// symbol name to its value and where it was defined
+type Symbols = HashMap<String, (u16, String)>;
+
+// a line of source that emits code or data, held between the two passes
+struct Statement<'a> {
+    address: usize,
+    mnemonic: &'a str,
+    operands: Vec<&'a str>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operand {
+    V(u8),
+    Literal(u16),
+    I,
+    IndirectI,
+    DT,
+    ST,
+    K,
+    F,
+    B,
+    HF,
+    R,
+    Pitch,
+    // vx-vy
+    Range(u8, u8),
+    // LONG addr, a whole word of address
+    Long(u16),
+}
+
+pub fn assemble_file(path: String) -> Result<Vec<u8>, AssemblerError> {
+    let source = fs::read_to_string(&path).map_err(|e| AssemblerError {
+        file: Some(path.clone()),
+        line: 0,
+        message: format!("cannot read source: {}", e),
+    })?;
+    assemble(&source, Some(&path))
+}
+
+// `path` is where the source came from, if anywhere, and is what includes are relative to
+pub fn assemble(source: &str, path: Option<&str>) -> Result<Vec<u8>, AssemblerError> {
+    assemble_with_map(source, path).map(|(rom, _)| rom)
+}
+
+// like assemble, but also say which line of source each address came from
+pub fn assemble_with_map(
+    source: &str,
+    path: Option<&str>,
+) -> Result<(Vec<u8>, SourceMap), AssemblerError> {
+    assemble_lines(&preprocess(source, path)?)
+}
+
+fn assemble_lines(lines: &[SourceLine]) -> Result<(Vec<u8>, SourceMap), AssemblerError> {
+    let mut symbols = Symbols::new();
+    let mut statements = Vec::new();
+    let mut address = RAM_OFFSET as usize;
+
+    // first pass: lay out every statement and find the value of each label and constant
+    for line in lines {
+        let error = |message| line.error(message);
+        let (label, statement) = parse_line(&line.text).map_err(error)?;
+        let is_equ = matches!(&statement, Some(s) if s.mnemonic.eq_ignore_ascii_case("equ"));
+        if let Some(label) = label {
+            check_symbol_name(label).map_err(error)?;
+            if let Some((_, first)) = symbols.get(label) {
+                return Err(error(format!(
+                    "duplicate symbol `{}` (first defined at {})",
+                    label, first
+                )));
+            }
+            let value = match &statement {
+                Some(statement) if is_equ => directive_value(statement, &symbols).map_err(error)?,
+                _ => address as u16,
+            };
+            symbols.insert(label.to_string(), (value, line.location()));
+        }
+        if let Some(mut statement) = statement {
+            match statement.mnemonic.to_lowercase().as_str() {
+                "equ" if label.is_none() => {
+                    return Err(error(
+                        "`equ` needs a name, as in `NAME equ value`".to_string(),
+                    ))
+                }
+                "equ" => (),
+                "org" => {
+                    let origin = directive_value(&statement, &symbols).map_err(error)? as usize;
+                    if origin < address {
+                        return Err(error(format!(
+                            "org {:#06x} is behind the current address {:#06x}",
+                            origin, address
+                        )));
+                    }
+                    address = origin;
+                }
+                "align" => {
+                    let alignment = directive_value(&statement, &symbols).map_err(error)? as usize;
+                    if alignment == 0 {
+                        return Err(error("cannot align to 0 bytes".to_string()));
+                    }
+                    address = address.div_ceil(alignment) * alignment;
+                }
+                _ => {
+                    statement.address = address;
+                    address += statement_size(&statement).map_err(error)?;
+                    statements.push((line, statement));
+                }
+            }
+            if address > 0x10000 {
+                return Err(error("program does not fit in memory".to_string()));
+            }
+        }
+    }
+
+    // second pass: encode, now that forward references can be resolved
+    let mut rom: Vec<u8> = Vec::new();
+    let mut map = SourceMap::default();
+    for (line, statement) in statements {
+        let bytes = assemble_statement(&statement, &symbols).map_err(|e| line.error(e))?;
+        map.statements.push(Mapping {
+            address: statement.address as u16,
+            size: bytes.len(),
+            file: line.file.as_deref().map(String::from),
+            line: line.line,
+        });
+        rom.resize(statement.address - RAM_OFFSET as usize, 0);
+        rom.extend(bytes);
+    }
+
+    Ok((rom, map))
+}
+
+// split a line of source into its label and statement, either of which may be missing
+fn parse_line(line: &str) -> Result<(Option<&str>, Option<Statement<'_>>), String> {
+    let mut code = strip_comment(line).trim();
+
+    let mut label = None;
+    if let Some(colon) = code.find(':') {
+        if !code[..colon].contains(char::is_whitespace) && !code[..colon].contains('"') {
+            label = Some(&code[..colon]);
+            code = code[colon + 1..].trim();
+        }
+    }
+    if code.is_empty() {
+        return Ok((label, None));
+    }
+
+    let (mut mnemonic, mut rest) = split_mnemonic(code);
+    // constants are written `NAME equ value`, with the name where a mnemonic would be
+    if label.is_none() {
+        let (second, after) = split_mnemonic(rest);
+        if second.eq_ignore_ascii_case("equ") {
+            label = Some(mnemonic);
+            mnemonic = second;
+            rest = after;
+        }
+    }
+    let operands = if rest.is_empty() {
+        Vec::new()
+    } else {
+        split_operands(rest)?
+    };
+
+    Ok((
+        label,
+        Some(Statement {
+            address: 0,
+            mnemonic,
+            operands,
+        }),
+    ))
+}
+
+fn split_mnemonic(code: &str) -> (&str, &str) {
+    match code.find(char::is_whitespace) {
+        Some(split) => (&code[..split], code[split..].trim()),
+        None => (code, ""),
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ';' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+// split operands on commas, except for those inside string literals
+fn split_operands(rest: &str) -> Result<Vec<&str>, String> {
+    let mut operands = Vec::new();
+    let mut start = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for (i, c) in rest.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            ',' if !in_string => {
+                operands.push(rest[start..i].trim());
+                start = i + 1;
+            }
+            _ => (),
+        }
+    }
+    if in_string {
+        return Err("unterminated string".to_string());
+    }
+    operands.push(rest[start..].trim());
+    Ok(operands)
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => {
+            (first.is_ascii_alphabetic() || first == '_')
+                && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+        }
+        None => false,
+    }
+}
+
+fn check_symbol_name(name: &str) -> Result<(), String> {
+    if !is_identifier(name) {
+        return Err(format!("invalid symbol name `{}`", name));
+    }
+    match parse_operand(name, &Symbols::new()) {
+        Ok(_) => Err(format!("`{}` is a reserved name", name)),
+        Err(_) => Ok(()),
+    }
+}
+
+// the number of bytes a statement will emit, worked out without resolving any symbols
+fn statement_size(statement: &Statement) -> Result<usize, String> {
+    let size = match statement.mnemonic.to_lowercase().as_str() {
+        "db" => {
+            let mut size = 0;
+            for operand in &statement.operands {
+                size += match parse_string(operand)? {
+                    Some(bytes) => bytes.len(),
+                    None => 1,
+                };
+            }
+            size
+        }
+        "dw" => 2 * statement.operands.len(),
+        "hex" => parse_hex_blob(&statement.operands)?.len(),
+        "sprite" => statement.operands.len(),
+        _ if statement
+            .operands
+            .iter()
+            .any(|operand| long_operand(operand).is_some()) =>
+        {
+            4
+        }
+        _ => 2,
+    };
+    Ok(size)
+}
+
+// the value of an org or align directive, which has to be known during the first pass
+fn directive_value(statement: &Statement, symbols: &Symbols) -> Result<u16, String> {
+    match statement.operands[..] {
+        [operand] => literal_value(operand, symbols),
+        _ => Err(format!(
+            "`{}` takes exactly one operand",
+            statement.mnemonic
+        )),
+    }
+}
+
+fn assemble_statement(statement: &Statement, symbols: &Symbols) -> Result<Vec<u8>, String> {
+    let mnemonic = statement.mnemonic;
+    let mut bytes = Vec::new();
+
+    match mnemonic.to_lowercase().as_str() {
+        "db" => {
+            if statement.operands.is_empty() {
+                return Err("`db` needs at least one operand".to_string());
+            }
+            for operand in &statement.operands {
+                match parse_string(operand)? {
+                    Some(string) => bytes.extend(string),
+                    None => bytes.push(byte(literal_value(operand, symbols)?)?),
+                }
+            }
+        }
+        "dw" => {
+            if statement.operands.is_empty() {
+                return Err("`dw` needs at least one operand".to_string());
+            }
+            for operand in &statement.operands {
+                let word = literal_value(operand, symbols)?;
+                bytes.push((word >> 8) as u8);
+                bytes.push(word as u8);
+            }
+        }
+        "hex" => bytes = parse_hex_blob(&statement.operands)?,
+        "sprite" => {
+            for operand in &statement.operands {
+                bytes.push(parse_sprite_row(operand)?);
+            }
+        }
+        _ => bytes = assemble_instruction(statement, symbols)?,
+    }
+
+    Ok(bytes)
+}
+
+fn assemble_instruction(statement: &Statement, symbols: &Symbols) -> Result<Vec<u8>, String> {
+    let mnemonic = statement.mnemonic;
+
+    // the decompiler emits words it can't decode as bare hexadecimal
+    if let Ok(word) = parse_number(mnemonic) {
+        if !statement.operands.is_empty() {
+            return Err(format!("unexpected operands after `{}`", mnemonic));
+        }
+        if word > 0xffff {
+            return Err(format!("{:#x} does not fit in a word", word));
+        }
+        return Ok((word as u16).to_be_bytes().to_vec());
+    }
+
+    let mut operands = Vec::new();
+    for operand in &statement.operands {
+        operands.push(parse_operand(operand, symbols)?);
+    }
+
+    encode(&mnemonic.to_uppercase(), &operands)
+}
+
+fn literal_value(operand: &str, symbols: &Symbols) -> Result<u16, String> {
+    match parse_operand(operand, symbols)? {
+        Operand::Literal(lit) => Ok(lit),
+        _ => Err(format!("expected a number or symbol, found `{}`", operand)),
+    }
+}
+
+// the bytes of a string literal, or None if the operand isn't one
+fn parse_string(operand: &str) -> Result<Option<Vec<u8>>, String> {
+    if !operand.starts_with('"') {
+        return Ok(None);
+    }
+    if operand.len() < 2 || !operand.ends_with('"') {
+        return Err(format!("malformed string {}", operand));
+    }
+
+    let mut bytes = Vec::new();
+    let mut chars = operand[1..operand.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('n') => '\n',
+                Some('0') => '\0',
+                Some(c @ '\\') | Some(c @ '"') => c,
+                _ => return Err(format!("invalid escape in string {}", operand)),
+            },
+            c => c,
+        };
+        if !c.is_ascii() {
+            return Err(format!("non-ASCII character `{}` in string", c));
+        }
+        bytes.push(c as u8);
+    }
+    Ok(Some(bytes))
+}
+
+// hex blobs are pairs of hex digits, optionally split up with whitespace
+fn parse_hex_blob(operands: &[&str]) -> Result<Vec<u8>, String> {
+    let digits: Vec<char> = operands
+        .iter()
+        .flat_map(|operand| operand.chars())
+        .filter(|c| !c.is_whitespace())
+        .collect();
+    if digits.is_empty() {
+        return Err("`hex` needs at least one byte".to_string());
+    }
+    if !digits.len().is_multiple_of(2) {
+        return Err("`hex` needs an even number of digits".to_string());
+    }
+
+    let mut bytes = Vec::new();
+    for pair in digits.chunks(2) {
+        let pair: String = pair.iter().collect();
+        match u8::from_str_radix(&pair, 16) {
+            Ok(byte) => bytes.push(byte),
+            Err(_) => return Err(format!("invalid hex byte `{}`", pair)),
+        }
+    }
+    Ok(bytes)
+}
+
+// a row of a sprite drawn with `#` for set pixels and `.` for clear ones, e.g. `##..##..`
+fn parse_sprite_row(row: &str) -> Result<u8, String> {
+    if row.is_empty() || row.len() > 8 {
+        return Err(format!("sprite row `{}` must be 1 to 8 pixels wide", row));
+    }
+
+    let mut byte = 0u8;
+    for (x, pixel) in row.chars().enumerate() {
+        match pixel {
+            '#' => byte |= 0b1000_0000 >> x,
+            '.' => (),
+            _ => return Err(format!("invalid pixel `{}` in sprite row", pixel)),
+        }
+    }
+    Ok(byte)
+}
+
+// the address part of a `LONG addr` operand
+fn long_operand(operand: &str) -> Option<&str> {
+    match operand.split_once(char::is_whitespace) {
+        Some((long, address)) if long.eq_ignore_ascii_case("long") => Some(address.trim()),
+        _ => None,
+    }
+}
+
+fn parse_register(operand: &str) -> Option<u8> {
+    match operand.strip_prefix(['v', 'V']) {
+        Some(reg) if reg.len() == 1 => u8::from_str_radix(reg, 16).ok(),
+        _ => None,
+    }
+}
+
+fn parse_operand(operand: &str, symbols: &Symbols) -> Result<Operand, String> {
+    if let Some(address) = long_operand(operand) {
+        return match parse_operand(address, symbols)? {
+            Operand::Literal(lit) => Ok(Operand::Long(lit)),
+            _ => Err(format!("invalid address `{}`", address)),
+        };
+    }
+    if let Some((x, y)) = operand.split_once('-') {
+        if let (Some(x), Some(y)) = (parse_register(x.trim()), parse_register(y.trim())) {
+            return Ok(Operand::Range(x, y));
+        }
+    }
+
+    let op = match operand.to_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::DT,
+        "ST" => Operand::ST,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        "HF" => Operand::HF,
+        "R" => Operand::R,
+        "PITCH" => Operand::Pitch,
+        reg if reg.len() == 2
+            && reg.starts_with('V')
+            && u8::from_str_radix(&reg[1..], 16).is_ok() =>
+        {
+            Operand::V(u8::from_str_radix(&reg[1..], 16).unwrap())
+        }
+        "" => return Err("missing operand".to_string()),
+        _ => match parse_number(operand) {
+            Ok(lit) if lit <= 0xffff => Operand::Literal(lit as u16),
+            Ok(_) => return Err(format!("literal `{}` is too large", operand)),
+            Err(_) => match symbols.get(operand) {
+                Some((value, _)) => Operand::Literal(*value),
+                None if is_identifier(operand) => {
+                    return Err(format!("undefined symbol `{}`", operand))
+                }
+                None => return Err(format!("invalid operand `{}`", operand)),
+            },
+        },
+    };
+    Ok(op)
+}
+
+// a number in hexadecimal (0x), binary (0b) or decimal
+pub fn parse_number(token: &str) -> Result<u32, std::num::ParseIntError> {
+    let lower = token.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x") {
+        u32::from_str_radix(hex, 16)
+    } else if let Some(bin) = lower.strip_prefix("0b") {
+        u32::from_str_radix(bin, 2)
+    } else {
+        lower.parse::<u32>()
+    }
+}
+
+fn addr(lit: u16) -> Result<u16, String> {
+    if lit > 0x0fff {
+        return Err(format!("address {:#06x} is out of range", lit));
+    }
+    Ok(lit)
+}
+
+fn byte(lit: u16) -> Result<u8, String> {
+    if lit > 0xff {
+        return Err(format!("byte {:#x} is out of range", lit));
+    }
+    Ok(lit as u8)
+}
+
+fn nibble(lit: u16) -> Result<u8, String> {
+    if lit > 0xf {
+        return Err(format!("nibble {:#x} is out of range", lit));
+    }
+    Ok(lit as u8)
+}
+
+fn encode(mnemonic: &str, operands: &[Operand]) -> Result<Vec<u8>, String> {
+    use Instruction::*;
+    use Operand::*;
+
+    let instruction = match (mnemonic, operands) {
+        // SYS is ignored by every interpreter we care about, so it has no instruction of its own
+        ("SYS", [Literal(a)]) => return Ok(addr(*a)?.to_be_bytes().to_vec()),
+        ("CLS", []) => Cls,
+        ("RET", []) => Ret,
+        ("JP", [Literal(a)]) => Jp(addr(*a)?),
+        ("CALL", [Literal(a)]) => Call(addr(*a)?),
+        ("SE", [V(vx), Literal(k)]) => SeByte(*vx, byte(*k)?),
+        ("SNE", [V(vx), Literal(k)]) => SneByte(*vx, byte(*k)?),
+        ("SE", [V(vx), V(vy)]) => SeReg(*vx, *vy),
+        ("LD", [V(vx), Literal(k)]) => LdByte(*vx, byte(*k)?),
+        ("ADD", [V(vx), Literal(k)]) => AddByte(*vx, byte(*k)?),
+        ("LD", [V(vx), V(vy)]) => LdReg(*vx, *vy),
+        ("OR", [V(vx), V(vy)]) => Or(*vx, *vy),
+        ("AND", [V(vx), V(vy)]) => And(*vx, *vy),
+        ("XOR", [V(vx), V(vy)]) => Xor(*vx, *vy),
+        ("ADD", [V(vx), V(vy)]) => AddReg(*vx, *vy),
+        ("SUB", [V(vx), V(vy)]) => Sub(*vx, *vy),
+        ("SHR", [V(vx)]) => Shr(*vx, 0),
+        ("SHR", [V(vx), V(vy)]) => Shr(*vx, *vy),
+        ("SUBN", [V(vx), V(vy)]) => Subn(*vx, *vy),
+        ("SHL", [V(vx)]) => Shl(*vx, 0),
+        ("SHL", [V(vx), V(vy)]) => Shl(*vx, *vy),
+        ("SNE", [V(vx), V(vy)]) => SneReg(*vx, *vy),
+        ("LD", [I, Literal(a)]) => LdI(addr(*a)?),
+        ("JP", [V(0), Literal(a)]) => JpV0(addr(*a)?),
+        ("RND", [V(vx), Literal(k)]) => Rnd(*vx, byte(*k)?),
+        ("DRW", [V(vx), V(vy), Literal(n)]) => Drw(*vx, *vy, nibble(*n)?),
+        ("SKP", [V(vx)]) => Skp(*vx),
+        ("SKNP", [V(vx)]) => Sknp(*vx),
+        ("LD", [V(vx), DT]) => LdVxDt(*vx),
+        ("LD", [V(vx), K]) => LdK(*vx),
+        ("LD", [DT, V(vx)]) => LdDtVx(*vx),
+        ("LD", [ST, V(vx)]) => LdStVx(*vx),
+        ("ADD", [I, V(vx)]) => AddI(*vx),
+        ("LD", [F, V(vx)]) => LdF(*vx),
+        ("LD", [B, V(vx)]) => LdB(*vx),
+        ("LD", [IndirectI, V(vx)]) => LdStore(*vx),
+        ("LD", [V(vx), IndirectI]) => LdRead(*vx),
+        ("SCD", [Literal(n)]) => Scd(nibble(*n)?),
+        ("SCR", []) => Scr,
+        ("SCL", []) => Scl,
+        ("EXIT", []) => Exit,
+        ("LOW", []) => Low,
+        ("HIGH", []) => High,
+        ("LD", [HF, V(vx)]) => LdHf(*vx),
+        ("LD", [R, V(vx)]) => LdRVx(*vx),
+        ("LD", [V(vx), R]) => LdVxR(*vx),
+        ("SCU", [Literal(n)]) => Scu(nibble(*n)?),
+        ("LD", [I, Long(a)]) => LdILong(*a),
+        ("LD", [IndirectI, Range(vx, vy)]) => SaveRange(*vx, *vy),
+        ("LD", [Range(vx, vy), IndirectI]) => LoadRange(*vx, *vy),
+        ("PLANE", [Literal(n)]) => Plane(nibble(*n)?),
+        ("AUDIO", []) => Audio,
+        ("LD", [Pitch, V(vx)]) => LdPitch(*vx),
+        (_, _) if !MNEMONICS.contains(&mnemonic) => {
+            return Err(format!("unknown mnemonic `{}`", mnemonic))
+        }
+        (_, _) => return Err(format!("invalid operands for `{}`", mnemonic)),
+    };
+    Ok(instruction::encode(instruction))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::decompiler::decompile_word;
+    use super::*;
+
+    fn assemble_line(line: &str) -> Result<Option<u16>, String> {
+        match parse_line(line)? {
+            (_, Some(statement)) => assemble_instruction(&statement, &Symbols::new())
+                .map(|bytes| Some(u16::from_be_bytes([bytes[0], bytes[1]]))),
+            (_, None) => Ok(None),
+        }
+    }
+
+    #[test]
+    fn test_parse_operand() {
+        assert_eq!(parse_operand("vA", &Symbols::new()), Ok(Operand::V(0xa)));
+        assert_eq!(parse_operand("V0", &Symbols::new()), Ok(Operand::V(0x0)));
+        assert_eq!(
+            parse_operand("[I]", &Symbols::new()),
+            Ok(Operand::IndirectI)
+        );
+        assert_eq!(
+            parse_operand("0x0234", &Symbols::new()),
+            Ok(Operand::Literal(0x234))
+        );
+        assert_eq!(
+            parse_operand("0b101", &Symbols::new()),
+            Ok(Operand::Literal(5))
+        );
+        assert_eq!(
+            parse_operand("12", &Symbols::new()),
+            Ok(Operand::Literal(12))
+        );
+        assert!(parse_operand("vG", &Symbols::new()).is_err());
+        assert!(parse_operand("0x10000", &Symbols::new()).is_err());
+
+        let mut symbols = Symbols::new();
+        symbols.insert("sprite".to_string(), (0x0300, "line 1".to_string()));
+        assert_eq!(
+            parse_operand("sprite", &symbols),
+            Ok(Operand::Literal(0x300))
+        );
+        assert_eq!(
+            parse_operand("missing", &symbols),
+            Err("undefined symbol `missing`".to_string())
+        );
+    }
+
+    #[test]
+    fn test_assemble_line() {
+        assert_eq!(assemble_line("JP   0x0234"), Ok(Some(0x1234)));
+        assert_eq!(assemble_line("LD   I,   0x0234"), Ok(Some(0xa234)));
+        assert_eq!(assemble_line("JP   V0,  0x0234"), Ok(Some(0xb234)));
+        assert_eq!(assemble_line("DRW  v0,  vE, 0x3"), Ok(Some(0xd0e3)));
+        assert_eq!(assemble_line("LD   [I], v2"), Ok(Some(0xf255)));
+        assert_eq!(assemble_line("ld v2, [i] ; load"), Ok(Some(0xf265)));
+        assert_eq!(assemble_line("SCD  0x4"), Ok(Some(0x00c4)));
+        assert_eq!(assemble_line("HIGH"), Ok(Some(0x00ff)));
+        assert_eq!(assemble_line("LD   HF,  v3"), Ok(Some(0xf330)));
+        assert_eq!(assemble_line("ld r, v3"), Ok(Some(0xf375)));
+        assert_eq!(assemble_line("LD   v3,  R"), Ok(Some(0xf385)));
+        assert_eq!(assemble_line("0x0123"), Ok(Some(0x0123)));
+        assert_eq!(assemble_line("   ; just a comment"), Ok(None));
+        assert_eq!(assemble_line(""), Ok(None));
+    }
+
+    #[test]
+    fn test_assemble_line_errors() {
+        assert!(assemble_line("FOO  v1").is_err());
+        assert!(assemble_line("CLS  v1").is_err());
+        assert!(assemble_line("JP   0x1000").is_err());
+        assert!(assemble_line("LD   v1,  0x100").is_err());
+        assert!(assemble_line("DRW  v0,  v1, 0x10").is_err());
+        assert!(assemble_line("JP   v1,  0x0200").is_err());
+        assert!(assemble_line("LD   v1,").is_err());
+    }
+
+    #[test]
+    fn test_assemble() {
+        let source = "CLS\nLD   v1,  0x02 ; comment\n\nJP   0x0200\n";
+        assert_eq!(
+            assemble(source, None),
+            Ok(vec![0x00, 0xe0, 0x61, 0x02, 0x12, 0x00])
+        );
+        assert_eq!(
+            assemble("CLS\nBAD", None),
+            Err(AssemblerError {
+                file: None,
+                line: 2,
+                message: "unknown mnemonic `BAD`".to_string()
+            })
+        );
+    }
+
+    #[test]
+    fn test_assemble_labels() {
+        let source = "start:\n    CALL draw\nloop: JP loop\ndraw:\n    LD   I, sprite\n    RET\nsprite: 0xf090";
+        assert_eq!(
+            assemble(source, None),
+            Ok(vec![
+                0x22, 0x04, 0x12, 0x02, 0xa2, 0x08, 0x00, 0xee, 0xf0, 0x90
+            ])
+        );
+        assert_eq!(
+            assemble("JP   start\nstart: CLS", None),
+            Ok(vec![0x12, 0x02, 0x00, 0xe0])
+        );
+    }
+
+    #[test]
+    fn test_assemble_xo_chip() {
+        let source = "LD   I,   LONG sprite\nLD   [I], v1-v3\nld v3-v1, [i]\nPLANE 0x3\nLD   PITCH, v2\norg 0x1000\nsprite: db 0xff";
+        let rom = assemble(source, None).unwrap();
+        assert_eq!(
+            rom[..12],
+            [0xf0, 0x00, 0x10, 0x00, 0x51, 0x32, 0x53, 0x13, 0xf3, 0x01, 0xf2, 0x3a]
+        );
+        assert_eq!(rom.len(), 0x1000 - 0x200 + 1);
+        assert!(assemble("LD   I,   LONG v1", None).is_err());
+    }
+
+    #[test]
+    fn test_assemble_label_errors() {
+        assert_eq!(
+            assemble("CLS\nJP   nowhere", None),
+            Err(AssemblerError {
+                file: None,
+                line: 2,
+                message: "undefined symbol `nowhere`".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("loop: CLS\nloop: RET", None),
+            Err(AssemblerError {
+                file: None,
+                line: 2,
+                message: "duplicate symbol `loop` (first defined at line 1)".to_string()
+            })
+        );
+        assert_eq!(
+            assemble("dt: CLS", None),
+            Err(AssemblerError {
+                file: None,
+                line: 1,
+                message: "`dt` is a reserved name".to_string()
+            })
+        );
+        assert!(assemble("2fast: CLS", None).is_err());
+    }
+
+    #[test]
+    fn test_assemble_data() {
+        assert_eq!(assemble("db 1, 0x02, 0b11", None), Ok(vec![1, 2, 3]));
+        assert_eq!(
+            assemble("db \"Hi, ;\\\"\", 0", None),
+            Ok(b"Hi, ;\"\0".to_vec())
+        );
+        assert_eq!(
+            assemble("dw 0x1234, end\nend:", None),
+            Ok(vec![0x12, 0x34, 0x02, 0x04])
+        );
+        assert_eq!(
+            assemble("hex F0 90,90F0", None),
+            Ok(vec![0xf0, 0x90, 0x90, 0xf0])
+        );
+        assert!(assemble("db 0x100", None).is_err());
+        assert!(assemble("db \"unterminated", None).is_err());
+        assert!(assemble("hex F0 9", None).is_err());
+        assert!(assemble("hex G0", None).is_err());
+    }
+
+    #[test]
+    fn test_assemble_sprite() {
+        let source = "LD   I, box\nbox: sprite ####\n     sprite #..#, #..#\n     sprite ########";
+        assert_eq!(
+            assemble(source, None),
+            Ok(vec![0xa2, 0x02, 0xf0, 0x90, 0x90, 0xff])
+        );
+        assert!(assemble("sprite #########", None).is_err());
+        assert!(assemble("sprite #x", None).is_err());
+    }
+
+    #[test]
+    fn test_assemble_org_align() {
+        assert_eq!(
+            assemble("db 1\norg 0x204\nthere: db 2\nJP there", None),
+            Ok(vec![1, 0, 0, 0, 2, 0x12, 0x04])
+        );
+        assert_eq!(
+            assemble("db 1\nalign 2\nhere: JP here", None),
+            Ok(vec![1, 0, 0x12, 0x02])
+        );
+        assert!(assemble("db 1, 2\norg 0x201", None).is_err());
+        assert!(assemble("org later\nlater:", None).is_err());
+        assert!(assemble("org 0xffff\nCLS", None).is_err());
+        assert!(assemble("align 0", None).is_err());
+    }
+
+    #[test]
+    fn test_assemble_constants() {
+        let source = "SPRITES equ 0x300\nROWS equ 5\nLD   I, SPRITES\nDRW  v0, v1, ROWS\norg SPRITES\ndb ROWS";
+        let mut expected = vec![0xa3, 0x00, 0xd0, 0x15];
+        expected.resize(0x100, 0);
+        expected.push(5);
+        assert_eq!(assemble(source, None), Ok(expected));
+        assert!(assemble("equ 5", None).is_err());
+        assert!(assemble("LATER equ later\nlater:", None).is_err());
+    }
+
+    #[test]
+    fn test_assemble_macros() {
+        let source = "define COUNTER v4\nmacro countdown from\n  LD   COUNTER, from\n@loop: ADD COUNTER, 0xff\n  SE   COUNTER, 0\n  JP   @loop\nendm\ncountdown 3\ncountdown 2";
+        assert_eq!(
+            assemble(source, None),
+            Ok(vec![
+                0x64, 0x03, 0x74, 0xff, 0x34, 0x00, 0x12, 0x02, 0x64, 0x02, 0x74, 0xff, 0x34, 0x00,
+                0x12, 0x0a
+            ])
+        );
+    }
+
+    #[test]
+    fn test_assemble_decompiled_words() {
+        for word in 0..=0xffffu16 {
+            let source = decompile_word(&word.to_be_bytes());
+            assert_eq!(
+                assemble(&source, None),
+                Ok(vec![(word >> 8) as u8, word as u8]),
+                "{}",
+                source
+            );
+        }
+    }
+
+    #[test]
+    fn test_assemble_missing_file() {
+        let error = assemble_file("missing.asm".to_string()).unwrap_err();
+        assert_eq!(
+            (error.file.as_deref(), error.line),
+            (Some("missing.asm"), 0)
+        );
+        assert!(error
+            .to_string()
+            .starts_with("missing.asm: cannot read source: "));
+    }
+
+    #[test]
+    fn test_source_map() {
+        let source = "start: CLS\n; nothing\nloop:\n  JP loop\norg 0x300\nsprite: db 0x01, 0x02\n";
+        let (rom, map) = assemble_with_map(source, None).unwrap();
+        assert_eq!(rom.len(), 0x102);
+        let lines: Vec<(u16, usize, usize)> = map
+            .statements
+            .iter()
+            .map(|mapping| (mapping.address, mapping.size, mapping.line))
+            .collect();
+        assert_eq!(lines, [(0x200, 2, 1), (0x202, 2, 4), (0x300, 2, 6)]);
+        assert_eq!(map.at_address(0x301).map(|m| m.line), Some(6));
+        assert_eq!(map.at_address(0x204), None);
+        assert_eq!(
+            map.at_line(2, |file| file.is_none()).map(|m| m.address),
+            Some(0x202)
+        );
+        assert_eq!(map.at_line(7, |file| file.is_none()), None);
+        assert_eq!(map.at_line(1, |file| file == Some("other.asm")), None);
+    }
+}
@@ -0,0 +1,323 @@
+use super::{
+    is_identifier, parse_line, parse_string, split_mnemonic, strip_comment, AssemblerError,
+};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+// how deeply includes and macro expansions may nest before we assume they recurse forever
+const MAX_DEPTH: usize = 32;
+
+// a line of source after includes, defines and macros have been expanded
+pub struct SourceLine {
+    pub file: Option<Rc<str>>,
+    pub line: usize,
+    pub text: String,
+}
+
+impl SourceLine {
+    pub fn location(&self) -> String {
+        match &self.file {
+            Some(file) => format!("{}:{}", file, self.line),
+            None => format!("line {}", self.line),
+        }
+    }
+
+    pub fn error(&self, message: String) -> AssemblerError {
+        AssemblerError {
+            file: self.file.as_deref().map(String::from),
+            line: self.line,
+            message,
+        }
+    }
+}
+
+#[derive(Clone)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+#[derive(Default)]
+struct Preprocessor {
+    defines: HashMap<String, String>,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+    lines: Vec<SourceLine>,
+}
+
+pub fn preprocess(source: &str, file: Option<&str>) -> Result<Vec<SourceLine>, AssemblerError> {
+    let mut preprocessor = Preprocessor::default();
+    preprocessor.process(source, file.map(Rc::from), 0)?;
+    Ok(preprocessor.lines)
+}
+
+impl Preprocessor {
+    fn process(
+        &mut self,
+        source: &str,
+        file: Option<Rc<str>>,
+        depth: usize,
+    ) -> Result<(), AssemblerError> {
+        let mut lines = source.lines().enumerate();
+        while let Some((i, line)) = lines.next() {
+            let location = SourceLine {
+                file: file.clone(),
+                line: i + 1,
+                text: String::new(),
+            };
+            let code = strip_comment(line).trim();
+            let (directive, rest) = split_mnemonic(code);
+
+            match directive.to_lowercase().as_str() {
+                "include" => {
+                    let path = match parse_string(rest).map_err(|e| location.error(e))? {
+                        Some(path) => String::from_utf8_lossy(&path).into_owned(),
+                        None => return Err(location.error("`include` needs a quoted path".into())),
+                    };
+                    let path = match file.as_deref().and_then(|f| Path::new(f).parent()) {
+                        Some(dir) => dir.join(path).to_string_lossy().into_owned(),
+                        None => path,
+                    };
+                    if depth >= MAX_DEPTH {
+                        return Err(location.error(format!("includes of {} nest too deeply", path)));
+                    }
+                    let included = fs::read_to_string(&path)
+                        .map_err(|e| location.error(format!("cannot include {}: {}", path, e)))?;
+                    self.process(&included, Some(Rc::from(path)), depth + 1)?;
+                }
+                "define" => {
+                    let (name, value) = split_mnemonic(rest);
+                    if !is_identifier(name) || value.is_empty() {
+                        return Err(location.error("expected `define NAME value`".into()));
+                    }
+                    self.defines.insert(name.to_string(), value.to_string());
+                }
+                "macro" => {
+                    let (name, params) = split_mnemonic(rest);
+                    if !is_identifier(name) {
+                        return Err(location.error(format!("invalid macro name `{}`", name)));
+                    }
+                    let mut parsed = Macro {
+                        params: Vec::new(),
+                        body: Vec::new(),
+                    };
+                    for param in params.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+                        if !is_identifier(param) {
+                            return Err(
+                                location.error(format!("invalid macro parameter `{}`", param))
+                            );
+                        }
+                        parsed.params.push(param.to_string());
+                    }
+
+                    loop {
+                        let body_line = match lines.next() {
+                            Some((_, body_line)) => body_line,
+                            None => {
+                                return Err(
+                                    location.error(format!("macro `{}` is missing `endm`", name))
+                                )
+                            }
+                        };
+                        let first = strip_comment(body_line).split_whitespace().next();
+                        match first.map(str::to_lowercase).as_deref() {
+                            Some("endm") => break,
+                            Some("macro") => {
+                                return Err(location.error(format!(
+                                    "macro `{}` is missing `endm` before the next macro",
+                                    name
+                                )))
+                            }
+                            _ => parsed.body.push(body_line.to_string()),
+                        }
+                    }
+                    self.macros.insert(name.to_string(), parsed);
+                }
+                "endm" => return Err(location.error("`endm` outside of a macro".into())),
+                _ => self.expand(line, &location, depth)?,
+            }
+        }
+        Ok(())
+    }
+
+    // substitute defines into a line, then expand it if it invokes a macro
+    fn expand(
+        &mut self,
+        line: &str,
+        location: &SourceLine,
+        depth: usize,
+    ) -> Result<(), AssemblerError> {
+        let defines = &self.defines;
+        let text = substitute(strip_comment(line), |word| defines.get(word).cloned());
+
+        let (label, statement) = parse_line(&text).map_err(|e| location.error(e))?;
+        let statement = match statement {
+            Some(statement) if self.macros.contains_key(statement.mnemonic) => statement,
+            _ => {
+                self.lines.push(SourceLine {
+                    file: location.file.clone(),
+                    line: location.line,
+                    text: text.clone(),
+                });
+                return Ok(());
+            }
+        };
+        let name = statement.mnemonic;
+        let args = statement.operands;
+        let invoked = self.macros[name].clone();
+
+        if depth >= MAX_DEPTH {
+            return Err(location.error(format!("expansion of macro `{}` nests too deeply", name)));
+        }
+        if args.len() != invoked.params.len() {
+            return Err(location.error(format!(
+                "macro `{}` takes {} arguments but was given {}",
+                name,
+                invoked.params.len(),
+                args.len()
+            )));
+        }
+        if let Some(label) = label {
+            self.lines.push(SourceLine {
+                file: location.file.clone(),
+                line: location.line,
+                text: format!("{}:", label),
+            });
+        }
+
+        // labels starting with @ are local to each expansion of the macro
+        self.expansions += 1;
+        let expansion = self.expansions;
+        let params: HashMap<&str, &str> = invoked
+            .params
+            .iter()
+            .map(String::as_str)
+            .zip(args.iter().copied())
+            .collect();
+        for body_line in &invoked.body {
+            let body_line = substitute(body_line, |word| match word.strip_prefix('@') {
+                Some(local) => Some(format!("__{}_{}_{}", name, expansion, local)),
+                None => params.get(word).map(|arg| arg.to_string()),
+            });
+            self.expand(&body_line, location, depth + 1)?;
+        }
+        Ok(())
+    }
+}
+
+// replace every whole word outside of string literals for which `replace` returns something
+fn substitute<F>(text: &str, replace: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut result = String::with_capacity(text.len());
+    let mut word = String::new();
+    let mut in_string = false;
+    let mut escaped = false;
+
+    let flush = |word: &mut String, result: &mut String| {
+        let starts_with_digit = word.starts_with(|c: char| c.is_ascii_digit());
+        match replace(word) {
+            Some(replacement) if !starts_with_digit => result.push_str(&replacement),
+            _ => result.push_str(word),
+        }
+        word.clear();
+    };
+
+    for c in text.chars() {
+        if !in_string && (c.is_ascii_alphanumeric() || c == '_' || c == '@') {
+            word.push(c);
+            continue;
+        }
+        flush(&mut word, &mut result);
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ => (),
+        }
+        result.push(c);
+    }
+    flush(&mut word, &mut result);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn texts(source: &str) -> Vec<String> {
+        preprocess(source, None)
+            .unwrap()
+            .into_iter()
+            .map(|line| line.text.trim().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_substitute() {
+        let replace = |word: &str| match word {
+            "x" => Some("v1".to_string()),
+            _ => None,
+        };
+        assert_eq!(substitute("LD x, 0x1x", replace), "LD v1, 0x1x");
+        assert_eq!(substitute("db \"x\", x", replace), "db \"x\", v1");
+        assert_eq!(substitute("xx x_", replace), "xx x_");
+    }
+
+    #[test]
+    fn test_define() {
+        assert_eq!(
+            texts("define PLAYER v3\nLD PLAYER, 1 ; comment"),
+            vec!["LD v3, 1"]
+        );
+    }
+
+    #[test]
+    fn test_macro() {
+        let source = "macro wait reg, ticks\n  LD   reg, ticks\n@spin: SE reg, 0\n  JP @spin\nendm\nstart: wait v1, 5\nwait v2, 6";
+        assert_eq!(
+            texts(source),
+            vec![
+                "start:",
+                "LD   v1, 5",
+                "__wait_1_spin: SE v1, 0",
+                "JP __wait_1_spin",
+                "LD   v2, 6",
+                "__wait_2_spin: SE v2, 0",
+                "JP __wait_2_spin",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_macro_errors() {
+        assert!(preprocess("macro m a\nCLS", None).is_err());
+        assert!(preprocess("endm", None).is_err());
+        assert!(preprocess("macro m a\nCLS\nendm\nm", None).is_err());
+        assert!(preprocess("macro m\nm\nendm\nm", None).is_err());
+    }
+
+    #[test]
+    fn test_include() {
+        let dir = std::env::temp_dir().join("chip8_preprocessor_test_include");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("inner.asm"), "define TWO 2\nLD v0, TWO").unwrap();
+        fs::write(dir.join("outer.asm"), "include \"inner.asm\"\nADD v0, TWO").unwrap();
+
+        let outer = dir.join("outer.asm").to_string_lossy().into_owned();
+        let source = fs::read_to_string(&outer).unwrap();
+        let lines = preprocess(&source, Some(&outer)).unwrap();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].text, "LD v0, 2");
+        assert!(lines[0].file.as_deref().unwrap().ends_with("inner.asm"));
+        assert_eq!(lines[1].text, "ADD v0, 2");
+        assert_eq!(lines[1].line, 2);
+
+        assert!(preprocess("include \"does/not/exist.asm\"", None).is_err());
+    }
+}
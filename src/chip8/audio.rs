@@ -0,0 +1,229 @@
+use std::f32::consts::PI;
+
+// the shape of the tone played while the sound timer is running
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sawtooth,
+    Sine,
+}
+
+impl std::str::FromStr for Waveform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "square" => Ok(Waveform::Square),
+            "triangle" => Ok(Waveform::Triangle),
+            "sawtooth" => Ok(Waveform::Sawtooth),
+            "sine" => Ok(Waveform::Sine),
+            _ => Err(format!("unknown waveform `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ToneSettings {
+    // in Hz
+    pub frequency: f32,
+    // from 0 (silent) to 1 (full scale)
+    pub volume: f32,
+    pub waveform: Waveform,
+    pub muted: bool,
+}
+
+impl Default for ToneSettings {
+    fn default() -> Self {
+        ToneSettings {
+            frequency: 440.0,
+            volume: 0.25,
+            waveform: Waveform::Square,
+            muted: false,
+        }
+    }
+}
+
+// makes the samples for the tone, independently of whatever plays them, so that sound can be
+// checked for without an audio device
+pub struct ToneGenerator {
+    settings: ToneSettings,
+    sample_rate: u32,
+    // how far through a cycle of the wave, or through the pattern, the next sample is, from 0 to 1
+    phase: f32,
+    playing: bool,
+    // XO-CHIP's 128 1-bit samples, played instead of the waveform, and how many of them play a
+    // second
+    pattern: Option<([u8; 16], f32)>,
+}
+
+// how many of an XO-CHIP pattern's samples play a second: 4000 at the default pitch of 64, an
+// octave higher or lower for every 48 above or below it
+pub fn pattern_rate(pitch: u8) -> f32 {
+    4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0)
+}
+
+impl ToneGenerator {
+    pub fn new(settings: ToneSettings, sample_rate: u32) -> ToneGenerator {
+        ToneGenerator {
+            settings,
+            sample_rate,
+            phase: 0.0,
+            playing: false,
+            pattern: None,
+        }
+    }
+
+    // play an XO-CHIP pattern at `pitch` instead of the waveform, or go back to the waveform
+    pub fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        self.pattern = pattern.map(|pattern| (*pattern, pattern_rate(pitch)));
+    }
+
+    pub fn start(&mut self) {
+        self.playing = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.playing = false;
+    }
+
+    pub fn playing(&self) -> bool {
+        self.playing
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.settings.muted = muted;
+    }
+
+    pub fn toggle_muted(&mut self) {
+        self.settings.muted = !self.settings.muted;
+    }
+
+    pub fn muted(&self) -> bool {
+        self.settings.muted
+    }
+
+    // fill `samples` with mono samples from -1 to 1, which are silence unless the tone is playing
+    pub fn fill(&mut self, samples: &mut [f32]) {
+        if !self.playing || self.settings.muted {
+            samples.iter_mut().for_each(|sample| *sample = 0.0);
+            return;
+        }
+        let step = match self.pattern {
+            Some((pattern, rate)) => rate / (pattern.len() * 8) as f32,
+            None => self.settings.frequency,
+        } / self.sample_rate as f32;
+        for sample in samples.iter_mut() {
+            *sample = self.settings.volume * self.wave(self.phase);
+            self.phase = (self.phase + step) % 1.0;
+        }
+    }
+
+    // the next `count` samples
+    pub fn samples(&mut self, count: usize) -> Vec<f32> {
+        let mut samples = vec![0.0; count];
+        self.fill(&mut samples);
+        samples
+    }
+
+    fn wave(&self, phase: f32) -> f32 {
+        if let Some((pattern, _)) = self.pattern {
+            // the most significant bit of each byte first
+            let bit = ((phase * 128.0) as usize).min(127);
+            return match pattern[bit / 8] >> (7 - bit % 8) & 1 {
+                1 => 1.0,
+                _ => -1.0,
+            };
+        }
+        match self.settings.waveform {
+            Waveform::Square if phase < 0.5 => 1.0,
+            Waveform::Square => -1.0,
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Sawtooth => 2.0 * phase - 1.0,
+            Waveform::Sine => (2.0 * PI * phase).sin(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::emulator::Chip8;
+    use super::*;
+
+    #[test]
+    fn test_square() {
+        // 4 samples to a cycle
+        let mut generator = ToneGenerator::new(ToneSettings::default(), 1760);
+        assert_eq!(generator.samples(4), [0.0; 4]);
+        generator.start();
+        assert_eq!(generator.samples(4), [0.25, 0.25, -0.25, -0.25]);
+        generator.toggle_muted();
+        assert_eq!(generator.samples(4), [0.0; 4]);
+    }
+
+    #[test]
+    fn test_waveforms() {
+        for (waveform, expected) in [
+            (Waveform::Triangle, [-1.0, 0.0, 1.0, 0.0]),
+            (Waveform::Sawtooth, [-1.0, -0.5, 0.0, 0.5]),
+        ] {
+            let settings = ToneSettings {
+                frequency: 100.0,
+                volume: 1.0,
+                waveform,
+                muted: false,
+            };
+            let mut generator = ToneGenerator::new(settings, 400);
+            generator.start();
+            assert_eq!(generator.samples(4), expected);
+        }
+        assert_eq!("sine".parse(), Ok(Waveform::Sine));
+        assert!("noise".parse::<Waveform>().is_err());
+    }
+
+    #[test]
+    fn test_pattern() {
+        let mut pattern = [0u8; 16];
+        pattern[0] = 0b1010_1100;
+        // one of the pattern's samples for every sample played
+        let mut generator = ToneGenerator::new(ToneSettings::default(), 4000);
+        generator.start();
+        generator.set_pattern(Some(&pattern), 64);
+        assert_eq!(
+            generator.samples(9),
+            [0.25, -0.25, 0.25, -0.25, 0.25, 0.25, -0.25, -0.25, -0.25]
+        );
+        // an octave up skips every other one
+        let mut generator = ToneGenerator::new(ToneSettings::default(), 4000);
+        generator.start();
+        generator.set_pattern(Some(&pattern), 112);
+        assert_eq!(generator.samples(4), [0.25, 0.25, 0.25, -0.25]);
+        assert_eq!(pattern_rate(16), 2000.0);
+        generator.set_pattern(None, 112);
+        assert!(generator
+            .samples(4)
+            .iter()
+            .all(|&sample| sample.abs() == 0.25));
+    }
+
+    #[test]
+    fn test_sound_timer() {
+        let mut chip8 = Chip8::new();
+        // LD v0, 0x02; LD ST, v0; JP 0x0204
+        chip8
+            .load_rom(&[0x60, 0x02, 0xf0, 0x18, 0x12, 0x04])
+            .unwrap();
+        let mut generator = ToneGenerator::new(ToneSettings::default(), 44100);
+        let mut beeps = Vec::new();
+        for _ in 0..4 {
+            chip8.frame().unwrap();
+            if chip8.sound_active() {
+                generator.start();
+            } else {
+                generator.stop();
+            }
+            beeps.push(generator.samples(735).iter().any(|&sample| sample != 0.0));
+        }
+        assert_eq!(beeps, [true, false, false, false]);
+    }
+}
@@ -0,0 +1,373 @@
+use super::assembler::parse_number;
+use super::emulator::Chip8Regs;
+use super::instruction::Address;
+
+use std::fmt;
+
+// a register that conditions can compare and watchpoints can watch
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reg {
+    V(u8),
+    I,
+    Pc,
+    Sp,
+    Dt,
+    St,
+}
+
+impl Reg {
+    pub fn value(self, registers: &Chip8Regs) -> i32 {
+        match self {
+            Reg::V(x) => registers.vx[x as usize] as i32,
+            Reg::I => registers.i as i32,
+            Reg::Pc => registers.pc as i32,
+            Reg::Sp => registers.sp as i32,
+            Reg::Dt => registers.dt as i32,
+            Reg::St => registers.st as i32,
+        }
+    }
+
+    fn format(self, value: i32) -> String {
+        match self {
+            Reg::V(_) | Reg::Dt | Reg::St => format!("{:#04x}", value),
+            Reg::I | Reg::Pc => format!("{:#06x}", value),
+            // -1 when the stack is empty
+            Reg::Sp => value.to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for Reg {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "i" => Ok(Reg::I),
+            "pc" => Ok(Reg::Pc),
+            "sp" => Ok(Reg::Sp),
+            "dt" => Ok(Reg::Dt),
+            "st" => Ok(Reg::St),
+            reg => match reg.strip_prefix('v').map(|x| u8::from_str_radix(x, 16)) {
+                Some(Ok(x)) if x < 16 => Ok(Reg::V(x)),
+                _ => Err(format!("unknown register `{}`", s)),
+            },
+        }
+    }
+}
+
+impl fmt::Display for Reg {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reg::V(x) => write!(f, "v{:X}", x),
+            Reg::I => write!(f, "I"),
+            Reg::Pc => write!(f, "pc"),
+            Reg::Sp => write!(f, "sp"),
+            Reg::Dt => write!(f, "dt"),
+            Reg::St => write!(f, "st"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Operand {
+    Reg(Reg),
+    Number(i32),
+}
+
+impl Operand {
+    fn parse(token: &str) -> Result<Operand, String> {
+        if let Ok(number) = parse_number(token) {
+            return Ok(Operand::Number(number as i32));
+        }
+        token
+            .parse()
+            .map(Operand::Reg)
+            .map_err(|_| format!("expected a register or a number, not `{}`", token))
+    }
+
+    fn value(self, registers: &Chip8Regs) -> i32 {
+        match self {
+            Operand::Reg(reg) => reg.value(registers),
+            Operand::Number(number) => number,
+        }
+    }
+}
+
+// the two character comparisons come first, so that `<=` isn't taken for `<`
+const COMPARISONS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+#[derive(Debug, Clone, PartialEq)]
+struct Comparison {
+    left: Operand,
+    op: &'static str,
+    right: Operand,
+}
+
+impl Comparison {
+    fn parse(text: &str) -> Result<Comparison, String> {
+        for op in COMPARISONS.iter() {
+            if let Some((left, right)) = text.split_once(op) {
+                return Ok(Comparison {
+                    left: Operand::parse(left.trim())?,
+                    op,
+                    right: Operand::parse(right.trim())?,
+                });
+            }
+        }
+        Err(format!("expected a comparison, not `{}`", text.trim()))
+    }
+
+    fn holds(&self, registers: &Chip8Regs) -> bool {
+        let (left, right) = (self.left.value(registers), self.right.value(registers));
+        match self.op {
+            "==" => left == right,
+            "!=" => left != right,
+            "<=" => left <= right,
+            ">=" => left >= right,
+            "<" => left < right,
+            _ => left > right,
+        }
+    }
+}
+
+// comparisons of registers and numbers joined by && and ||, such as `v0 == 5 && I > 0x300`.
+// && binds tighter than ||
+#[derive(Debug, Clone, PartialEq)]
+pub struct Condition {
+    text: String,
+    any: Vec<Vec<Comparison>>,
+}
+
+impl Condition {
+    pub fn holds(&self, registers: &Chip8Regs) -> bool {
+        self.any
+            .iter()
+            .any(|all| all.iter().all(|comparison| comparison.holds(registers)))
+    }
+}
+
+impl std::str::FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let any = s
+            .split("||")
+            .map(|all| all.split("&&").map(Comparison::parse).collect())
+            .collect::<Result<_, _>>()?;
+        Ok(Condition {
+            text: s.split_whitespace().collect::<Vec<_>>().join(" "),
+            any,
+        })
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+// stops the machine before the instruction at `address`, if the condition holds there
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub address: Address,
+    pub condition: Option<Condition>,
+}
+
+impl Breakpoint {
+    pub fn new(address: Address) -> Breakpoint {
+        Breakpoint {
+            address,
+            condition: None,
+        }
+    }
+
+    pub fn when(address: Address, condition: Condition) -> Breakpoint {
+        Breakpoint {
+            address,
+            condition: Some(condition),
+        }
+    }
+
+    pub fn hit(&self, registers: &Chip8Regs) -> bool {
+        match &self.condition {
+            Some(condition) => registers.pc == self.address && condition.holds(registers),
+            None => registers.pc == self.address,
+        }
+    }
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.condition {
+            Some(condition) => write!(f, "{:#06x} if {}", self.address, condition),
+            None => write!(f, "{:#06x}", self.address),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+// stops the machine after an instruction that touches some memory or changes a register. Only
+// what instructions read and write as data counts, not fetching the instructions themselves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Watchpoint {
+    // the bytes from start to end, inclusive
+    Memory {
+        start: Address,
+        end: Address,
+        reads: bool,
+        writes: bool,
+    },
+    Register(Reg),
+}
+
+impl Watchpoint {
+    pub fn watches(&self, address: Address, access: Access) -> bool {
+        match *self {
+            Watchpoint::Memory {
+                start,
+                end,
+                reads,
+                writes,
+            } => {
+                (start..=end).contains(&address)
+                    && match access {
+                        Access::Read => reads,
+                        Access::Write => writes,
+                    }
+            }
+            Watchpoint::Register(_) => false,
+        }
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Watchpoint::Memory {
+                start,
+                end,
+                reads,
+                writes,
+            } => {
+                let accesses = match (reads, writes) {
+                    (true, true) => "reads of and writes to",
+                    (true, false) => "reads of",
+                    _ => "writes to",
+                };
+                if start == end {
+                    write!(f, "{} {:#06x}", accesses, start)
+                } else {
+                    write!(f, "{} {:#06x}-{:#06x}", accesses, start, end)
+                }
+            }
+            Watchpoint::Register(reg) => write!(f, "changes to {}", reg),
+        }
+    }
+}
+
+// why the last instruction stopped the machine. `pc` is the address of that instruction
+#[derive(Debug, Clone, PartialEq)]
+pub enum Hit {
+    Breakpoint(Address),
+    Memory {
+        pc: Address,
+        address: Address,
+        access: Access,
+        value: u8,
+    },
+    Register {
+        pc: Address,
+        reg: Reg,
+        old: i32,
+        new: i32,
+    },
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Hit::Breakpoint(address) => write!(f, "Breakpoint at {:#06x}", address),
+            Hit::Memory {
+                pc,
+                address,
+                access: Access::Read,
+                value,
+            } => write!(f, "{:#06x} read {:#04x} from {:#06x}", pc, value, address),
+            Hit::Memory {
+                pc,
+                address,
+                access: Access::Write,
+                value,
+            } => write!(f, "{:#06x} wrote {:#04x} to {:#06x}", pc, value, address),
+            Hit::Register { pc, reg, old, new } => write!(
+                f,
+                "{:#06x} changed {} from {} to {}",
+                pc,
+                reg,
+                reg.format(old),
+                reg.format(new)
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_condition() {
+        let mut registers = Chip8Regs {
+            i: 0x301,
+            ..Chip8Regs::default()
+        };
+        registers.vx[0] = 5;
+        let condition: Condition = "v0 == 5 && I > 0x300".parse().unwrap();
+        assert!(condition.holds(&registers));
+        registers.i = 0x300;
+        assert!(!condition.holds(&registers));
+        let condition: Condition = "v0!=5 || i<=0x300".parse().unwrap();
+        assert!(condition.holds(&registers));
+        assert_eq!(condition.to_string(), "v0!=5 || i<=0x300");
+        assert!("vG == 1".parse::<Condition>().is_err());
+        assert!("v0".parse::<Condition>().is_err());
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let mut registers = Chip8Regs {
+            pc: 0x208,
+            ..Chip8Regs::default()
+        };
+        assert!(Breakpoint::new(0x208).hit(&registers));
+        let breakpoint = Breakpoint::when(0x208, "v3 >= 2".parse().unwrap());
+        assert!(!breakpoint.hit(&registers));
+        registers.vx[3] = 2;
+        assert!(breakpoint.hit(&registers));
+        assert_eq!(breakpoint.to_string(), "0x0208 if v3 >= 2");
+    }
+
+    #[test]
+    fn test_watchpoint() {
+        let watchpoint = Watchpoint::Memory {
+            start: 0x300,
+            end: 0x30f,
+            reads: false,
+            writes: true,
+        };
+        assert!(watchpoint.watches(0x30f, Access::Write));
+        assert!(!watchpoint.watches(0x310, Access::Write));
+        assert!(!watchpoint.watches(0x300, Access::Read));
+        assert_eq!(watchpoint.to_string(), "writes to 0x0300-0x030f");
+        assert_eq!(
+            Watchpoint::Register("vb".parse().unwrap()).to_string(),
+            "changes to vB"
+        );
+    }
+}
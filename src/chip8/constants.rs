@@ -1,10 +1,15 @@
-pub const CELL_W: u32 = 16; // in pixels
-pub const CELL_H: u32 = 16; // in pixels
+pub const CELL_W: u32 = 8; // in pixels, for a hi-res cell
+pub const CELL_H: u32 = 8; // in pixels, for a hi-res cell
 pub const CHIP8_DISP_W: u32 = 64; // in cells (chip8 pixels)
 pub const CHIP8_DISP_H: u32 = 32; // in cells (chip8 pixels)
-pub const FPS: u64 = 600;
+pub const SCHIP_DISP_W: u32 = 128; // in cells, in the SUPER-CHIP's hi-res mode
+pub const SCHIP_DISP_H: u32 = 64; // in cells, in the SUPER-CHIP's hi-res mode
+pub const FRAME_RATE: u32 = 60; // the display and timers run at 60Hz
+pub const INSTRUCTIONS_PER_FRAME: u32 = 10; // unless set otherwise, i.e. 600 instructions a second
+pub const REWIND_DEPTH: usize = 36_000; // instructions to keep for stepping back, a minute's worth
+pub const REWIND_BUDGET: usize = 16; // in MiB, the most the history of instructions may take up
 pub const RAM_OFFSET: u16 = 0x0200; // offset in the ram where user programs start
-pub const DEBUG: bool = true;
+pub const BIG_FONT_OFFSET: u16 = 0x0050; // where the big font goes in RAM, right after FONT
 pub const FONT: [u8; 80] = [
     0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
     0x20, 0x60, 0x20, 0x20, 0x70, // 1
@@ -23,3 +28,21 @@ pub const FONT: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
+pub const BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
@@ -0,0 +1,739 @@
+use super::assembler::{assemble_with_map, SourceMap};
+use super::breakpoint::{Breakpoint, Hit};
+use super::decompiler::decompile_word;
+use super::emulator::{Chip8, Chip8Error};
+use super::frontend::Frontend;
+use super::instruction::{decode_at, Address, Instruction};
+
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::iter;
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+// there's only the one thread of execution
+const THREAD_ID: u64 = 1;
+
+// the longest message body that's read, far more than any request this needs, so that a bad
+// Content-Length can't make it allocate whatever it likes
+const MAX_MESSAGE_SIZE: usize = 1 << 20;
+
+// the variablesReference of each scope
+const REGISTERS: u64 = 1;
+const TIMERS: u64 = 2;
+const STACK: u64 = 3;
+const KEYPAD: u64 = 4;
+
+// the next message, which comes after a Content-Length header, or None once the input ends
+pub fn read_message<R: BufRead>(input: &mut R) -> io::Result<Option<Value>> {
+    let invalid = |e| io::Error::new(io::ErrorKind::InvalidData, e);
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        match header.trim() {
+            "" if length.is_some() => break,
+            header => {
+                if let Some(value) = header.strip_prefix("Content-Length:") {
+                    length = Some(
+                        value
+                            .trim()
+                            .parse::<usize>()
+                            .map_err(|e| invalid(e.to_string()))?,
+                    );
+                }
+            }
+        }
+    }
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE_SIZE {
+        return Err(invalid(format!(
+            "a message of {} bytes is longer than the {} allowed",
+            length, MAX_MESSAGE_SIZE
+        )));
+    }
+    let mut body = vec![0u8; length];
+    input.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid(e.to_string()))
+}
+
+// read messages on a thread of their own, so that they can arrive while the ROM is running
+pub fn spawn_reader<R: BufRead + Send + 'static>(mut input: R) -> Receiver<Value> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        while let Ok(Some(message)) = read_message(&mut input) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+pub fn write_message<W: Write>(output: &mut W, message: &Value) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+// how far to let the ROM run
+#[derive(Clone, Copy, PartialEq)]
+enum Run {
+    Continue,
+    // a single instruction
+    Step,
+    // a single instruction, or a whole subroutine if it's a CALL
+    Next,
+    // until the current subroutine returns
+    StepOut,
+    // a single instruction backwards
+    StepBack,
+    // backwards until a breakpoint, or as far back as the history goes
+    ReverseContinue,
+}
+
+// what to do once a request has been answered
+enum Flow {
+    Stay,
+    Run(Run),
+    Quit,
+}
+
+// a server for the Debug Adapter Protocol, which lets editors debug a ROM assembled from source,
+// with breakpoints set on lines of the source
+pub struct DapServer<W: Write> {
+    messages: Receiver<Value>,
+    output: W,
+    seq: u64,
+    map: SourceMap,
+    // the addresses of the breakpoints set in each file, by its canonical path
+    breakpoints: HashMap<PathBuf, Vec<Address>>,
+    stop_on_entry: bool,
+    // a request that came in while the ROM was running
+    pending: Option<Value>,
+}
+
+impl<W: Write> DapServer<W> {
+    pub fn new(messages: Receiver<Value>, output: W) -> DapServer<W> {
+        DapServer {
+            messages,
+            output,
+            seq: 0,
+            map: SourceMap::default(),
+            breakpoints: HashMap::new(),
+            stop_on_entry: false,
+            pending: None,
+        }
+    }
+
+    // answer requests until the editor disconnects. `chip8` is set up as the ROM should run, and
+    // the ROM is loaded into it when the editor launches one
+    pub fn serve<F: Frontend>(&mut self, chip8: &mut Chip8, frontend: &mut F) -> io::Result<()> {
+        loop {
+            let request = match self.pending.take() {
+                Some(request) => request,
+                None => match self.messages.recv() {
+                    Ok(request) => request,
+                    Err(_) => return Ok(()),
+                },
+            };
+            match self.handle(chip8, &request)? {
+                Flow::Stay => (),
+                Flow::Run(run) => {
+                    if self.run(chip8, frontend, run)? {
+                        return Ok(());
+                    }
+                }
+                Flow::Quit => return Ok(()),
+            }
+        }
+    }
+
+    fn handle(&mut self, chip8: &mut Chip8, request: &Value) -> io::Result<Flow> {
+        let args = &request["arguments"];
+        let command = request["command"].as_str().unwrap_or_default();
+        let (body, flow) = match command {
+            "initialize" => (
+                Ok(json!({
+                    "supportsConfigurationDoneRequest": true,
+                    "supportsConditionalBreakpoints": true,
+                    "supportsStepBack": chip8.history().is_some(),
+                })),
+                Flow::Stay,
+            ),
+            "launch" => (self.launch(chip8, args), Flow::Stay),
+            "setBreakpoints" => (self.set_breakpoints(chip8, args), Flow::Stay),
+            "configurationDone" if self.stop_on_entry => (Ok(Value::Null), Flow::Stay),
+            "configurationDone" => (Ok(Value::Null), Flow::Run(Run::Continue)),
+            "threads" => (
+                Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "CHIP-8" }] })),
+                Flow::Stay,
+            ),
+            "stackTrace" => (Ok(self.stack_trace(chip8)), Flow::Stay),
+            "scopes" => (Ok(scopes()), Flow::Stay),
+            "variables" => (
+                Ok(variables(chip8, args["variablesReference"].as_u64())),
+                Flow::Stay,
+            ),
+            "continue" => (
+                Ok(json!({ "allThreadsContinued": true })),
+                Flow::Run(Run::Continue),
+            ),
+            "next" => (Ok(Value::Null), Flow::Run(Run::Next)),
+            "stepIn" => (Ok(Value::Null), Flow::Run(Run::Step)),
+            "stepOut" => (Ok(Value::Null), Flow::Run(Run::StepOut)),
+            "stepBack" | "reverseContinue" if chip8.history().is_none() => (
+                Err("stepping back needs a rewind depth".to_string()),
+                Flow::Stay,
+            ),
+            "stepBack" => (Ok(Value::Null), Flow::Run(Run::StepBack)),
+            "reverseContinue" => (Ok(Value::Null), Flow::Run(Run::ReverseContinue)),
+            "pause" => (Ok(Value::Null), Flow::Stay),
+            "disconnect" | "terminate" => (Ok(Value::Null), Flow::Quit),
+            _ => (Err(format!("`{}` isn't supported", command)), Flow::Stay),
+        };
+        let launched = command == "launch" && body.is_ok();
+        self.respond(request, body)?;
+        match command {
+            // breakpoints can only be placed once there's a source map
+            _ if launched => self.event("initialized", Value::Null)?,
+            "configurationDone" if self.stop_on_entry => self.stopped(chip8, "entry", None)?,
+            "pause" => self.stopped(chip8, "pause", None)?,
+            _ => (),
+        }
+        Ok(flow)
+    }
+
+    // assemble the program and load it, leaving it to run once the editor has set it up
+    fn launch(&mut self, chip8: &mut Chip8, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or("launching needs the path of the `program`")?;
+        let source =
+            fs::read_to_string(program).map_err(|e| format!("cannot read {}: {}", program, e))?;
+        let (rom, map) = assemble_with_map(&source, Some(program)).map_err(|e| e.to_string())?;
+        chip8.load_rom(&rom).map_err(|e| e.to_string())?;
+        self.map = map;
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(Value::Null)
+    }
+
+    // replace the breakpoints in a file, each of which moves to the first line with code on or
+    // after it
+    fn set_breakpoints(&mut self, chip8: &mut Chip8, args: &Value) -> Result<Value, String> {
+        let path = args["source"]["path"]
+            .as_str()
+            .ok_or("breakpoints need the `path` of their source")?;
+        let path = canonical(path);
+        for address in self.breakpoints.remove(&path).unwrap_or_default() {
+            chip8.remove_breakpoint(address);
+        }
+        let mut addresses = Vec::new();
+        let mut results = Vec::new();
+        let requested = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        for breakpoint in requested {
+            let line = breakpoint["line"].as_u64().unwrap_or(0) as usize;
+            let condition = match breakpoint["condition"].as_str().map(str::trim) {
+                Some(condition) if !condition.is_empty() => condition.parse().map(Some),
+                _ => Ok(None),
+            };
+            let mapping = self.map.at_line(
+                line,
+                |file| matches!(file, Some(file) if canonical(file) == path),
+            );
+            let result = match (mapping, condition) {
+                (Some(mapping), Ok(condition)) => {
+                    chip8.add_breakpoint(Breakpoint {
+                        address: mapping.address,
+                        condition,
+                    });
+                    addresses.push(mapping.address);
+                    json!({ "verified": true, "line": mapping.line })
+                }
+                (None, _) => json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "there's no code on or after this line",
+                }),
+                (_, Err(e)) => json!({ "verified": false, "line": line, "message": e }),
+            };
+            results.push(result);
+        }
+        self.breakpoints.insert(path, addresses);
+        Ok(json!({ "breakpoints": results }))
+    }
+
+    // pc, then where each subroutine being run was called from, innermost first
+    fn stack_trace(&self, chip8: &Chip8) -> Value {
+        let addresses = iter::once(chip8.registers().pc).chain(chip8.stack().iter().rev().copied());
+        let frames: Vec<Value> = addresses
+            .enumerate()
+            .map(|(id, address)| {
+                let name = format!(
+                    "{:#06x}  {}",
+                    address,
+                    decompile_word(&chip8.fetch(address))
+                );
+                let mut frame = json!({ "id": id, "name": name, "line": 0, "column": 0 });
+                let mapping = self.map.at_address(address);
+                if let Some((file, line)) = mapping.and_then(|m| Some((m.file.as_ref()?, m.line))) {
+                    frame["source"] = json!({ "path": canonical(file) });
+                    frame["line"] = json!(line);
+                    frame["column"] = json!(1);
+                }
+                frame
+            })
+            .collect();
+        json!({ "totalFrames": frames.len(), "stackFrames": frames })
+    }
+
+    // let the ROM run, answering requests as they come in, until it stops. Returns whether the
+    // editor disconnected meanwhile
+    fn run<F: Frontend>(
+        &mut self,
+        chip8: &mut Chip8,
+        frontend: &mut F,
+        run: Run,
+    ) -> io::Result<bool> {
+        let pc = chip8.registers().pc;
+        let depth = chip8.stack().len();
+        let run = match (run, decode_at(&chip8.fetch(pc))) {
+            (Run::Next, Ok(Instruction::Call(_))) => Run::Next,
+            (Run::Next, _) => Run::Step,
+            (run, _) => run,
+        };
+        if let Run::Step | Run::StepBack | Run::ReverseContinue = run {
+            let stepped = match run {
+                Run::StepBack => {
+                    chip8.step_back();
+                    Ok(())
+                }
+                Run::ReverseContinue => {
+                    chip8.reverse_continue();
+                    Ok(())
+                }
+                _ => chip8.step(),
+            };
+            if chip8.draw_flag() {
+                frontend.present(chip8.framebuffer());
+            }
+            self.stopped(chip8, "step", stepped.err())?;
+            return Ok(false);
+        }
+        loop {
+            let messages = &self.messages;
+            let pending = &mut self.pending;
+            let result = chip8.run_until(frontend, |chip8| {
+                let done = match run {
+                    // back from the subroutine, just past the CALL
+                    Run::Next => {
+                        chip8.registers().pc == pc.wrapping_add(2) && chip8.stack().len() == depth
+                    }
+                    Run::StepOut => chip8.stack().len() < depth,
+                    _ => false,
+                };
+                if pending.is_none() {
+                    *pending = messages.try_recv().ok();
+                }
+                done || pending.is_some() || chip8.trap().is_some()
+            });
+            let request = match self.pending.take() {
+                Some(request) => request,
+                None => {
+                    return match result {
+                        Ok(false) if !chip8.exited() => {
+                            // the front-end was asked to quit
+                            self.event("terminated", Value::Null)?;
+                            Ok(true)
+                        }
+                        result => {
+                            self.stopped(chip8, "step", result.err())?;
+                            Ok(false)
+                        }
+                    };
+                }
+            };
+            match self.handle(chip8, &request)? {
+                Flow::Quit => return Ok(true),
+                // a pause has already been answered with a stop
+                _ if request["command"] == "pause" => return Ok(false),
+                // anything else is answered while the ROM carries on
+                _ => (),
+            }
+        }
+    }
+
+    // tell the editor that the ROM has stopped. `reason` is why, unless the ROM itself stopped
+    // it, by faulting, hitting a breakpoint or a watchpoint, or exiting
+    fn stopped(
+        &mut self,
+        chip8: &Chip8,
+        reason: &str,
+        error: Option<Chip8Error>,
+    ) -> io::Result<()> {
+        if chip8.exited() {
+            self.event("exited", json!({ "exitCode": 0 }))?;
+            return self.event("terminated", Value::Null);
+        }
+        let (reason, description) = match (error.as_ref().or_else(|| chip8.trap()), chip8.hit()) {
+            (Some(fault), _) => ("exception", Some(fault.to_string())),
+            (None, Some(Hit::Breakpoint(_))) => ("breakpoint", None),
+            (None, Some(hit)) => ("data breakpoint", Some(hit.to_string())),
+            (None, None) => (reason, None),
+        };
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+        }
+        self.event("stopped", body)
+    }
+
+    fn respond(&mut self, request: &Value, body: Result<Value, String>) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": body.is_ok(),
+        });
+        match body {
+            Ok(Value::Null) => (),
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        write_message(&mut self.output, &message)
+    }
+}
+
+// the path that the editor knows a file by
+fn canonical(path: &str) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+fn scopes() -> Value {
+    let scope = |name: &str, reference: u64| json!({ "name": name, "variablesReference": reference, "expensive": false });
+    json!({
+        "scopes": [
+            scope("Registers", REGISTERS),
+            scope("Timers", TIMERS),
+            scope("Stack", STACK),
+            scope("Keypad", KEYPAD),
+        ]
+    })
+}
+
+fn variables(chip8: &Chip8, reference: Option<u64>) -> Value {
+    let registers = chip8.registers();
+    let values: Vec<(String, String)> = match reference {
+        Some(REGISTERS) => registers
+            .vx
+            .iter()
+            .enumerate()
+            .map(|(x, value)| (format!("V{:X}", x), format!("{:#04x}", value)))
+            .chain(vec![
+                ("I".to_string(), format!("{:#06x}", registers.i)),
+                ("PC".to_string(), format!("{:#06x}", registers.pc)),
+            ])
+            .collect(),
+        Some(TIMERS) => vec![
+            ("DT".to_string(), registers.dt.to_string()),
+            ("ST".to_string(), registers.st.to_string()),
+        ],
+        // innermost first, as in the call stack
+        Some(STACK) => chip8
+            .stack()
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(depth, address)| (format!("#{}", depth), format!("{:#06x}", address)))
+            .collect(),
+        Some(KEYPAD) => (0..16)
+            .map(|key| {
+                let state = if chip8.keys() & (1 << key) != 0 {
+                    "down"
+                } else {
+                    "up"
+                };
+                (format!("{:X}", key), state.to_string())
+            })
+            .collect(),
+        _ => Vec::new(),
+    };
+    let variables: Vec<Value> = values
+        .into_iter()
+        .map(|(name, value)| json!({ "name": name, "value": value, "variablesReference": 0 }))
+        .collect();
+    json!({ "variables": variables })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::mpsc::Sender;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    // never quits, so that the ROM runs until the client stops it
+    struct OpenFrontend;
+
+    impl Frontend for OpenFrontend {
+        fn present(&mut self, _planes: [&[u128]; 2]) {}
+
+        fn poll_input(&mut self) -> Option<u16> {
+            Some(0)
+        }
+
+        fn start_tone(&mut self) {}
+
+        fn stop_tone(&mut self) {}
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // an editor, talking to a server on a thread of its own
+    struct Client {
+        requests: Sender<Value>,
+        output: SharedOutput,
+        seq: u64,
+        // how many of the server's messages have been looked at
+        seen: usize,
+        server: thread::JoinHandle<()>,
+    }
+
+    impl Client {
+        fn new() -> Client {
+            let (requests, messages) = mpsc::channel();
+            let output = SharedOutput::default();
+            let server_output = output.clone();
+            let server = thread::spawn(move || {
+                let mut chip8 = Chip8::new();
+                chip8.set_rewind(1000, 1 << 20);
+                DapServer::new(messages, server_output)
+                    .serve(&mut chip8, &mut OpenFrontend)
+                    .unwrap();
+            });
+            Client {
+                requests,
+                output,
+                seq: 0,
+                seen: 0,
+                server,
+            }
+        }
+
+        // the first message from the server that hasn't been looked at and `wanted` is true for
+        fn wait_for<P: Fn(&Value) -> bool>(&mut self, wanted: P) -> Value {
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(10) {
+                let bytes = self.output.0.lock().unwrap().clone();
+                let mut input = Cursor::new(bytes);
+                let mut messages = Vec::new();
+                // the last message may only be partly written
+                while let Ok(Some(message)) = read_message(&mut input) {
+                    messages.push(message);
+                }
+                if let Some(index) = (self.seen..messages.len()).find(|&i| wanted(&messages[i])) {
+                    self.seen = index + 1;
+                    return messages.swap_remove(index);
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            panic!("the server didn't send what was wanted");
+        }
+
+        fn request(&mut self, command: &str, arguments: Value) -> Value {
+            self.seq += 1;
+            let seq = self.seq;
+            let request = json!({
+                "seq": seq,
+                "type": "request",
+                "command": command,
+                "arguments": arguments,
+            });
+            self.requests.send(request).unwrap();
+            self.wait_for(|message| message["request_seq"] == seq)
+        }
+
+        fn event(&mut self, event: &str) -> Value {
+            self.wait_for(|message| message["event"] == event)
+        }
+
+        fn stopped(&mut self) -> String {
+            self.event("stopped")["body"]["reason"]
+                .as_str()
+                .unwrap()
+                .to_string()
+        }
+
+        fn top_line(&mut self) -> Value {
+            self.request("stackTrace", json!({ "threadId": THREAD_ID }))["body"]["stackFrames"][0]
+                ["line"]
+                .clone()
+        }
+
+        fn variable(&mut self, reference: u64, name: &str) -> Value {
+            let response = self.request("variables", json!({ "variablesReference": reference }));
+            let variables = response["body"]["variables"].as_array().unwrap();
+            let variable = variables.iter().find(|v| v["name"] == name).unwrap();
+            variable["value"].clone()
+        }
+    }
+
+    const SOURCE: &str = "\
+; counts up in v0
+start:  LD   v0, 0x05
+loop:   ADD  v0, 0x01
+        CALL sub
+        JP   loop
+sub:    RET
+";
+
+    fn source_file() -> String {
+        let path = std::env::temp_dir().join("chip8_dap_test.asm");
+        fs::write(&path, SOURCE).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_read_write_message() {
+        let mut output = Vec::new();
+        write_message(&mut output, &json!({ "seq": 1 })).unwrap();
+        assert_eq!(output, b"Content-Length: 9\r\n\r\n{\"seq\":1}");
+        let mut input = Cursor::new(output);
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "seq": 1 })));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+        let mut input = Cursor::new(b"Content-Length: 99999999999\r\n\r\n{}".to_vec());
+        let error = read_message(&mut input).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_session() {
+        let path = source_file();
+        let mut client = Client::new();
+        let response = client.request("initialize", json!({ "adapterID": "chip8" }));
+        assert_eq!(response["body"]["supportsConditionalBreakpoints"], true);
+        assert_eq!(response["body"]["supportsStepBack"], true);
+        let response = client.request("launch", json!({ "program": "missing.asm" }));
+        assert_eq!(response["success"], false);
+        assert_eq!(
+            client.request("launch", json!({ "program": path }))["success"],
+            true
+        );
+        client.event("initialized");
+
+        let response = client.request(
+            "setBreakpoints",
+            json!({
+                "source": { "path": path },
+                "breakpoints": [{ "line": 1 }, { "line": 6 }, { "line": 40 }],
+            }),
+        );
+        let lines: Vec<(Value, Value)> = response["body"]["breakpoints"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| (b["verified"].clone(), b["line"].clone()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (json!(true), json!(2)),
+                (json!(true), json!(6)),
+                (json!(false), json!(40)),
+            ]
+        );
+        client.request("configurationDone", json!({}));
+        assert_eq!(client.stopped(), "breakpoint");
+        assert_eq!(client.top_line(), 2);
+        client.request("continue", json!({ "threadId": THREAD_ID }));
+        assert_eq!(client.stopped(), "breakpoint");
+        let response = client.request("stackTrace", json!({ "threadId": THREAD_ID }));
+        let frames = response["body"]["stackFrames"].as_array().unwrap();
+        assert_eq!(frames[0]["name"], "0x0208  RET");
+        assert_eq!(frames[0]["line"], 6);
+        assert_eq!(frames[1]["line"], 4);
+        assert_eq!(client.variable(REGISTERS, "V0"), "0x06");
+        assert_eq!(client.variable(STACK, "#0"), "0x0204");
+        assert_eq!(client.variable(KEYPAD, "5"), "up");
+
+        client.request("setBreakpoints", json!({ "source": { "path": path } }));
+        // RET, JP and ADD one at a time, then the whole of the subroutine
+        for line in [5, 3, 4, 5] {
+            client.request("next", json!({ "threadId": THREAD_ID }));
+            assert_eq!(client.stopped(), "step");
+            assert_eq!(client.top_line(), line);
+        }
+        assert_eq!(client.variable(REGISTERS, "V0"), "0x07");
+
+        client.request("continue", json!({ "threadId": THREAD_ID }));
+        client.request("pause", json!({ "threadId": THREAD_ID }));
+        assert_eq!(client.stopped(), "pause");
+
+        let response = client.request(
+            "setBreakpoints",
+            json!({
+                "source": { "path": path },
+                "breakpoints": [{ "line": 6, "condition": "v0 == 0x40" }, { "line": 3, "condition": "v0 =" }],
+            }),
+        );
+        assert_eq!(response["body"]["breakpoints"][1]["verified"], false);
+        client.request("continue", json!({ "threadId": THREAD_ID }));
+        assert_eq!(client.stopped(), "breakpoint");
+        assert_eq!(client.variable(REGISTERS, "V0"), "0x40");
+        client.request("stepOut", json!({ "threadId": THREAD_ID }));
+        assert_eq!(client.stopped(), "step");
+        assert_eq!(client.top_line(), 5);
+
+        client.request("stepBack", json!({ "threadId": THREAD_ID }));
+        assert_eq!(client.stopped(), "step");
+        assert_eq!(client.top_line(), 6);
+        client.request(
+            "setBreakpoints",
+            json!({ "source": { "path": path }, "breakpoints": [{ "line": 3 }] }),
+        );
+        client.request("reverseContinue", json!({ "threadId": THREAD_ID }));
+        assert_eq!(client.stopped(), "breakpoint");
+        assert_eq!(client.top_line(), 3);
+        assert_eq!(client.variable(REGISTERS, "V0"), "0x3f");
+
+        client.request("disconnect", json!({}));
+        client.server.join().unwrap();
+    }
+}
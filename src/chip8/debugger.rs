@@ -0,0 +1,549 @@
+use super::assembler::parse_number;
+use super::breakpoint::{Breakpoint, Reg, Watchpoint};
+use super::decompiler::decompile_word;
+use super::emulator::Chip8;
+use super::frontend::Frontend;
+use super::instruction::{size_at, Address};
+
+use std::io::{self, BufRead, Write};
+
+const HELP: &str = "\
+step [n]           execute n instructions (1 unless given)
+continue           run until a breakpoint, a watchpoint, a trap or the ROM exits
+back [n]           undo n instructions (1 unless given)
+reverse            undo instructions until a breakpoint, or as far back as the history goes
+break [addr]       stop before the instruction at addr, or list the breakpoints
+break <addr> if <condition>
+                   stop there only when the condition holds, as in `v0 == 5 && I > 0x300`
+delete <addr>      remove a breakpoint
+watch [target]     stop after writes to an address or range (`0x300-0x30f`), or changes to a
+                   register, or list the watchpoints
+rwatch <target>    stop after reads of an address or range
+awatch <target>    stop after reads of or writes to an address or range
+unwatch <n>        remove watchpoint n
+regs               show the registers
+mem <addr> <len>   dump len bytes of memory from addr
+stack              show the return addresses on the stack, innermost first
+disasm [addr] [n]  disassemble n instructions (10 unless given) from addr (pc unless given)
+set <reg> <value>  set v0-vF, i, pc, dt or st
+quit               stop debugging
+An empty line repeats the last command.";
+
+// what the debugger does once a command has run
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    Show(String),
+    Quit,
+}
+
+// a command-line debugger for a ROM, run in the terminal alongside the front-end
+pub struct Debugger {
+    last_command: String,
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            last_command: String::new(),
+        }
+    }
+
+    // read commands from `input` until it runs out or one of them quits, writing what they show
+    // to `output`
+    pub fn repl<F: Frontend, R: BufRead, W: Write>(
+        &mut self,
+        chip8: &mut Chip8,
+        frontend: &mut F,
+        mut input: R,
+        mut output: W,
+    ) -> io::Result<()> {
+        frontend.present(chip8.framebuffer());
+        writeln!(output, "{}", location(chip8))?;
+        loop {
+            write!(output, "(chip8) ")?;
+            output.flush()?;
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(());
+            }
+            if !line.trim().is_empty() {
+                self.last_command = line.trim().to_string();
+            }
+            let command = self.last_command.clone();
+            match self.command(chip8, frontend, &command) {
+                Ok(Reply::Show(text)) => writeln!(output, "{}", text)?,
+                Ok(Reply::Quit) => return Ok(()),
+                Err(e) => writeln!(output, "error: {}", e)?,
+            }
+        }
+    }
+
+    pub fn command<F: Frontend>(
+        &mut self,
+        chip8: &mut Chip8,
+        frontend: &mut F,
+        line: &str,
+    ) -> Result<Reply, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let text = match words[..] {
+            [] => String::new(),
+            ["step"] | ["s"] => step(chip8, frontend, 1)?,
+            ["step", n] | ["s", n] => step(chip8, frontend, value(n, 0xffff)?)?,
+            ["continue"] | ["c"] => resume(chip8, frontend)?,
+            ["back"] => back(chip8, frontend, 1)?,
+            ["back", n] => back(chip8, frontend, value(n, 0xffff)?)?,
+            ["reverse"] => reverse(chip8, frontend)?,
+            ["break"] | ["b"] => list(chip8.breakpoints(), "No breakpoints"),
+            ["break", address] | ["b", address] => {
+                let breakpoint = Breakpoint::new(value(address, 0xffff)? as Address);
+                add_breakpoint(chip8, breakpoint)
+            }
+            ["break", address, "if", ref condition @ ..]
+            | ["b", address, "if", ref condition @ ..] => {
+                let address = value(address, 0xffff)? as Address;
+                let breakpoint = Breakpoint::when(address, condition.join(" ").parse()?);
+                add_breakpoint(chip8, breakpoint)
+            }
+            ["delete", address] => {
+                let address = value(address, 0xffff)? as Address;
+                if !chip8.remove_breakpoint(address) {
+                    return Err(format!("no breakpoint at {:#06x}", address));
+                }
+                format!("Deleted the breakpoint at {:#06x}", address)
+            }
+            ["watch"] => list(chip8.watchpoints(), "No watchpoints"),
+            ["watch", target] => add_watchpoint(chip8, target, false, true)?,
+            ["rwatch", target] => add_watchpoint(chip8, target, true, false)?,
+            ["awatch", target] => add_watchpoint(chip8, target, true, true)?,
+            ["unwatch", index] => match chip8.remove_watchpoint(value(index, 0xffff)? as usize) {
+                Some(watchpoint) => format!("Deleted the watchpoint on {}", watchpoint),
+                None => return Err(format!("no watchpoint #{}", index)),
+            },
+            ["regs"] | ["r"] => registers(chip8),
+            ["mem", address, length] | ["m", address, length] => memory(
+                chip8,
+                value(address, 0xffff)?,
+                value(length, chip8.memory().len() as u32)?,
+            ),
+            ["stack"] => stack(chip8),
+            ["disasm"] | ["d"] => disassemble(chip8, chip8.registers().pc, 10),
+            ["disasm", address] | ["d", address] => {
+                disassemble(chip8, value(address, 0xffff)? as Address, 10)
+            }
+            ["disasm", address, count] | ["d", address, count] => disassemble(
+                chip8,
+                value(address, 0xffff)? as Address,
+                value(count, 0xffff)?,
+            ),
+            ["set", register, number] => set(chip8, register, number)?,
+            ["help"] | ["h"] => HELP.to_string(),
+            ["quit"] | ["q"] => return Ok(Reply::Quit),
+            _ => return Err(format!("unknown command `{}`, try `help`", line)),
+        };
+        Ok(Reply::Show(text))
+    }
+}
+
+// a number no bigger than `max`
+fn value(token: &str, max: u32) -> Result<u32, String> {
+    match parse_number(token) {
+        Ok(number) if number <= max => Ok(number),
+        Ok(_) => Err(format!("{} is too large, the most is {:#x}", token, max)),
+        Err(_) => Err(format!("invalid number `{}`", token)),
+    }
+}
+
+// the instruction about to run, as in `0x0202  LD   I,   0x0300`
+fn location(chip8: &Chip8) -> String {
+    let pc = chip8.registers().pc;
+    format!("{:#06x}  {}", pc, decompile_word(&chip8.fetch(pc)))
+}
+
+// why the ROM stopped, if there's more to it than having come to the next instruction
+fn stopped(chip8: &Chip8) -> String {
+    match (chip8.trap(), chip8.hit()) {
+        (Some(trap), _) => format!("Trapped: {}\n{}", trap, location(chip8)),
+        (None, Some(hit)) => format!("{}\n{}", hit, location(chip8)),
+        (None, None) if chip8.exited() => "The ROM has exited".to_string(),
+        (None, None) => location(chip8),
+    }
+}
+
+fn step<F: Frontend>(chip8: &mut Chip8, frontend: &mut F, count: u32) -> Result<String, String> {
+    for _ in 0..count {
+        chip8.step().map_err(|e| e.to_string())?;
+        if chip8.trap().is_some() || chip8.hit().is_some() || chip8.exited() {
+            break;
+        }
+    }
+    if chip8.draw_flag() {
+        frontend.present(chip8.framebuffer());
+    }
+    Ok(stopped(chip8))
+}
+
+// run in real time from where the ROM stopped until something stops it again
+fn resume<F: Frontend>(chip8: &mut Chip8, frontend: &mut F) -> Result<String, String> {
+    if chip8.exited() {
+        return Err("the ROM has exited".to_string());
+    }
+    chip8
+        .run_until(frontend, |chip8| chip8.trap().is_some())
+        .map_err(|e| e.to_string())?;
+    // if none of those, the front-end was asked to quit
+    Ok(stopped(chip8))
+}
+
+fn back<F: Frontend>(chip8: &mut Chip8, frontend: &mut F, count: u32) -> Result<String, String> {
+    if chip8.history().is_none() {
+        return Err("stepping back needs a rewind depth".to_string());
+    }
+    let undone = (0..count).take_while(|_| chip8.step_back()).count();
+    frontend.present(chip8.framebuffer());
+    if undone == 0 {
+        return Err("there's nothing left to undo".to_string());
+    }
+    Ok(location(chip8))
+}
+
+fn reverse<F: Frontend>(chip8: &mut Chip8, frontend: &mut F) -> Result<String, String> {
+    if chip8.history().is_none() {
+        return Err("stepping back needs a rewind depth".to_string());
+    }
+    let hit = chip8.reverse_continue();
+    frontend.present(chip8.framebuffer());
+    if hit {
+        Ok(stopped(chip8))
+    } else {
+        Ok(format!(
+            "Back at the start of the history\n{}",
+            location(chip8)
+        ))
+    }
+}
+
+fn add_breakpoint(chip8: &mut Chip8, breakpoint: Breakpoint) -> String {
+    let text = format!("Breakpoint at {}", breakpoint);
+    chip8.add_breakpoint(breakpoint);
+    text
+}
+
+// watch `target`, which is a register, an address or a range of them like `0x300-0x30f`
+fn add_watchpoint(
+    chip8: &mut Chip8,
+    target: &str,
+    reads: bool,
+    writes: bool,
+) -> Result<String, String> {
+    let watchpoint = match target.parse::<Reg>() {
+        Ok(reg) if !reads => Watchpoint::Register(reg),
+        Ok(_) => return Err("only changes to registers can be watched".to_string()),
+        Err(_) => {
+            let (start, end) = target.split_once('-').unwrap_or((target, target));
+            let (start, end) = (value(start, 0xffff)?, value(end, 0xffff)?);
+            if end < start {
+                return Err(format!("{} ends before it starts", target));
+            }
+            Watchpoint::Memory {
+                start: start as Address,
+                end: end as Address,
+                reads,
+                writes,
+            }
+        }
+    };
+    let text = format!(
+        "Watchpoint #{} on {}",
+        chip8.watchpoints().len(),
+        watchpoint
+    );
+    chip8.add_watchpoint(watchpoint);
+    Ok(text)
+}
+
+// one numbered line for each breakpoint or watchpoint
+fn list<T: std::fmt::Display>(items: &[T], none: &str) -> String {
+    if items.is_empty() {
+        return none.to_string();
+    }
+    let lines: Vec<String> = items
+        .iter()
+        .enumerate()
+        .map(|(index, item)| format!("#{}  {}", index, item))
+        .collect();
+    lines.join("\n")
+}
+
+fn registers(chip8: &Chip8) -> String {
+    let registers = chip8.registers();
+    let vx: Vec<String> = registers
+        .vx
+        .iter()
+        .enumerate()
+        .map(|(x, value)| format!("v{:X}: {:#04x}", x, value))
+        .collect();
+    format!(
+        "{}\n{}\nI: {:#06x}  pc: {:#06x}  sp: {}  dt: {}  st: {}",
+        vx[..8].join("  "),
+        vx[8..].join("  "),
+        registers.i,
+        registers.pc,
+        registers.sp,
+        registers.dt,
+        registers.st
+    )
+}
+
+// 16 bytes to a line, each line starting with its address
+fn memory(chip8: &Chip8, address: u32, length: u32) -> String {
+    let memory = chip8.memory();
+    let start = address as usize;
+    let end = (start + length as usize).min(memory.len());
+    let lines: Vec<String> = (start..end)
+        .step_by(16)
+        .map(|line| {
+            let bytes: Vec<String> = memory[line..(line + 16).min(end)]
+                .iter()
+                .map(|byte| format!("{:02x}", byte))
+                .collect();
+            format!("{:#06x}  {}", line, bytes.join(" "))
+        })
+        .collect();
+    lines.join("\n")
+}
+
+fn stack(chip8: &Chip8) -> String {
+    if chip8.stack().is_empty() {
+        return "The stack is empty".to_string();
+    }
+    let entries: Vec<String> = chip8
+        .stack()
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(depth, address)| format!("#{}  {:#06x}", depth, address))
+        .collect();
+    entries.join("\n")
+}
+
+// `count` instructions from `address`, with the one at pc marked
+fn disassemble(chip8: &Chip8, mut address: Address, count: u32) -> String {
+    let mut lines = Vec::new();
+    for _ in 0..count {
+        let code = chip8.fetch(address);
+        let marker = if address == chip8.registers().pc {
+            "=>"
+        } else {
+            "  "
+        };
+        lines.push(format!(
+            "{} {:#06x}  {}",
+            marker,
+            address,
+            decompile_word(&code)
+        ));
+        address = address.wrapping_add(size_at(&code) as Address);
+    }
+    lines.join("\n")
+}
+
+fn set(chip8: &mut Chip8, register: &str, number: &str) -> Result<String, String> {
+    let mut registers = chip8.registers().clone();
+    match register.parse()? {
+        Reg::I => registers.i = value(number, 0xffff)? as u16,
+        Reg::Pc => registers.pc = value(number, 0xffff)? as u16,
+        Reg::Dt => registers.dt = value(number, 0xff)? as u8,
+        Reg::St => registers.st = value(number, 0xff)? as u8,
+        Reg::V(x) => registers.vx[x as usize] = value(number, 0xff)? as u8,
+        // the stack itself would have to change with it
+        Reg::Sp => return Err("sp can't be set".to_string()),
+    }
+    chip8.set_registers(registers).map_err(|e| e.to_string())?;
+    Ok(format!("{} = {}", register, number))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::frontend::testing::{QuitFrontend, ROM};
+    use super::*;
+    use std::io::Cursor;
+
+    fn debug(commands: &[&str]) -> Vec<Result<Reply, String>> {
+        let mut chip8 = Chip8::new();
+        chip8.set_rewind(100, 1 << 20);
+        chip8.load_rom(&ROM).unwrap();
+        let mut frontend = QuitFrontend::default();
+        let mut debugger = Debugger::new();
+        commands
+            .iter()
+            .map(|command| debugger.command(&mut chip8, &mut frontend, command))
+            .collect()
+    }
+
+    fn show(text: &str) -> Result<Reply, String> {
+        Ok(Reply::Show(text.to_string()))
+    }
+
+    #[test]
+    fn test_step() {
+        assert_eq!(
+            debug(&["step", "s 2", "stack"]),
+            [
+                show("0x0202  ADD  v0,  0x01"),
+                show("0x0208  RET"),
+                show("#0  0x0204"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_back_and_reverse() {
+        let replies = debug(&[
+            "s 4",
+            "back 2",
+            "regs",
+            "break 0x202",
+            "c",
+            "back",
+            "reverse",
+        ]);
+        assert_eq!(replies[1], show("0x0204  CALL 0x0208"));
+        match &replies[2] {
+            Ok(Reply::Show(regs)) => assert!(regs.contains("pc: 0x0204  sp: -1")),
+            reply => panic!("{:?}", reply),
+        }
+        assert_eq!(
+            replies[4],
+            show("Breakpoint at 0x0202\n0x0202  ADD  v0,  0x01")
+        );
+        assert_eq!(replies[5], show("0x0206  JP   0x0202"));
+        assert_eq!(
+            replies[6],
+            show("Breakpoint at 0x0202\n0x0202  ADD  v0,  0x01")
+        );
+        assert_eq!(
+            debug(&["s", "reverse"])[1],
+            show("Back at the start of the history\n0x0200  LD   v0,  0x05")
+        );
+        assert!(debug(&["back"])[0].is_err());
+    }
+
+    #[test]
+    fn test_break_continue() {
+        let replies = debug(&["break 0x0208", "b", "continue", "c", "delete 0x0208", "b"]);
+        assert_eq!(replies[0], show("Breakpoint at 0x0208"));
+        assert_eq!(replies[1], show("#0  0x0208"));
+        assert_eq!(replies[2], show("Breakpoint at 0x0208\n0x0208  RET"));
+        assert_eq!(replies[3], show("Breakpoint at 0x0208\n0x0208  RET"));
+        assert_eq!(replies[5], show("No breakpoints"));
+        assert!(debug(&["delete 0x0208"])[0].is_err());
+
+        // one on the first instruction stops before it runs, then lets it run on the next continue
+        let replies = debug(&["break 0x200", "c", "c"]);
+        assert_eq!(
+            replies[1],
+            show("Breakpoint at 0x0200\n0x0200  LD   v0,  0x05")
+        );
+        assert_ne!(replies[2], replies[1]);
+    }
+
+    #[test]
+    fn test_conditional_break() {
+        let replies = debug(&["break 0x208 if v0 == 8 && sp >= 0", "c", "regs"]);
+        assert_eq!(
+            replies[0],
+            show("Breakpoint at 0x0208 if v0 == 8 && sp >= 0")
+        );
+        assert_eq!(replies[1], show("Breakpoint at 0x0208\n0x0208  RET"));
+        match &replies[2] {
+            Ok(Reply::Show(regs)) => assert!(regs.starts_with("v0: 0x08")),
+            reply => panic!("{:?}", reply),
+        }
+        assert!(debug(&["break 0x208 if v0 = 8"])[0].is_err());
+    }
+
+    #[test]
+    fn test_watch() {
+        let replies = debug(&[
+            "watch v0",
+            "rwatch 0x300-0x30f",
+            "watch",
+            "c",
+            "unwatch 0",
+            "watch",
+        ]);
+        assert_eq!(replies[0], show("Watchpoint #0 on changes to v0"));
+        assert_eq!(replies[1], show("Watchpoint #1 on reads of 0x0300-0x030f"));
+        assert_eq!(
+            replies[2],
+            show("#0  changes to v0\n#1  reads of 0x0300-0x030f")
+        );
+        assert_eq!(
+            replies[3],
+            show("0x0200 changed v0 from 0x00 to 0x05\n0x0202  ADD  v0,  0x01")
+        );
+        assert_eq!(replies[4], show("Deleted the watchpoint on changes to v0"));
+        assert_eq!(replies[5], show("#0  reads of 0x0300-0x030f"));
+        assert!(debug(&["rwatch v0"])[0].is_err());
+        assert!(debug(&["watch 0x30f-0x300"])[0].is_err());
+        assert!(debug(&["unwatch 0"])[0].is_err());
+    }
+
+    #[test]
+    fn test_regs_and_set() {
+        let replies = debug(&[
+            "set v3 0x10",
+            "set i 0x300",
+            "set vG 1",
+            "set v3 0x100",
+            "regs",
+        ]);
+        assert_eq!(replies[0], show("v3 = 0x10"));
+        assert!(replies[2].is_err());
+        assert!(replies[3].is_err());
+        assert_eq!(
+            replies[4],
+            show("v0: 0x00  v1: 0x00  v2: 0x00  v3: 0x10  v4: 0x00  v5: 0x00  v6: 0x00  v7: 0x00\nv8: 0x00  v9: 0x00  vA: 0x00  vB: 0x00  vC: 0x00  vD: 0x00  vE: 0x00  vF: 0x00\nI: 0x0300  pc: 0x0200  sp: -1  dt: 0  st: 0")
+        );
+    }
+
+    #[test]
+    fn test_mem_and_disasm() {
+        assert_eq!(
+            debug(&["mem 0x200 18"])[0],
+            show("0x0200  60 05 70 01 22 08 12 02 00 ee 00 00 00 00 00 00\n0x0210  00 00")
+        );
+        assert_eq!(
+            debug(&["disasm 0x200 3"])[0],
+            show("=> 0x0200  LD   v0,  0x05\n   0x0202  ADD  v0,  0x01\n   0x0204  CALL 0x0208")
+        );
+        assert!(debug(&["mem 0x200"])[0].is_err());
+        assert!(debug(&["frobnicate"])[0].is_err());
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&ROM).unwrap();
+        let mut frontend = QuitFrontend::default();
+        let mut output = Vec::new();
+        Debugger::new()
+            .repl(
+                &mut chip8,
+                &mut frontend,
+                Cursor::new("step\n\nbogus\nquit\nstep\n"),
+                &mut output,
+            )
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "0x0200  LD   v0,  0x05\n(chip8) 0x0202  ADD  v0,  0x01\n(chip8) 0x0204  CALL 0x0208\n(chip8) error: unknown command `bogus`, try `help`\n(chip8) "
+        );
+    }
+}
@@ -1,279 +1,614 @@
+use super::analysis;
 use super::constants::RAM_OFFSET;
+use super::emulator::{Chip8Error, MAX_ROM_SIZE};
+use super::instruction::{decode_at, size_at};
 
-use std::fs::File;
-use std::io::Read;
-use std::io::{BufReader, ErrorKind};
-
-pub fn decompile_rom(path: String) -> String {
-    let file = File::open(path).expect("Cannot Read ROM");
-    let mut buf = BufReader::new(file);
-    let mut rom_bytes = [0; (0x1000 - RAM_OFFSET as usize)];
-    let mut decomp: Vec<String> = Vec::new();
-
-    match buf.read(&mut rom_bytes) {
-        Ok(0) => (println! {"No bytes read from ROM!"}),
-        Ok(n) => {
-            for word in rom_bytes.iter().take(n).collect::<Vec<_>>().chunks(2) {
-                decomp.push(decompile_word(*word[0], *word[1]));
-            }
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fs;
+
+#[derive(Default)]
+pub struct DecompileOptions {
+    // emit source that reassembles byte-for-byte to the original ROM
+    pub round_trip: bool,
+    // decode every word in order instead of following control flow from the entry point
+    pub linear_sweep: bool,
+    // prefix each line with its address and raw bytes; the result no longer reassembles
+    pub listing: bool,
+}
+
+pub fn decompile_rom(path: String, options: &DecompileOptions) -> Result<String, Chip8Error> {
+    Ok(decompile_bytes(&read_rom(path)?, options))
+}
+
+// the whole of a ROM, which it's an error for there to be nothing in
+pub fn read_rom(path: String) -> Result<Vec<u8>, Chip8Error> {
+    let rom_bytes = fs::read(path)?;
+    if rom_bytes.is_empty() {
+        return Err(Chip8Error::EmptyRom);
+    }
+    if rom_bytes.len() > MAX_ROM_SIZE {
+        return Err(Chip8Error::RomTooLarge(rom_bytes.len()));
+    }
+    Ok(rom_bytes)
+}
+
+// how a span of the ROM is laid out in the decompiled source
+enum Line {
+    // the offset and size of an instruction
+    Instruction(usize, usize),
+    Data(usize, usize),
+}
+
+struct Label {
+    name: String,
+    refs: Vec<u16>,
+}
+
+// a line of output, before it's formatted as either source or a listing
+struct Row<'a> {
+    // labels have no address of their own
+    offset: Option<usize>,
+    bytes: &'a [u8],
+    text: String,
+    comment: Option<String>,
+}
+
+pub fn decompile_bytes(rom: &[u8], options: &DecompileOptions) -> String {
+    let (layout, sprite) = if options.linear_sweep {
+        (layout_linear(rom), vec![false; rom.len()])
+    } else {
+        let analysis = analysis::analyse(rom);
+        (layout_code(rom, &analysis.code), analysis.sprite)
+    };
+    let labels = find_labels(rom, &layout);
+
+    let mut rows: Vec<Row> = Vec::new();
+    let emit_label = |rows: &mut Vec<Row>, offset: usize| {
+        if let Some(label) = address_of(offset).and_then(|address| labels.get(&address)) {
+            let refs: Vec<String> = label.refs.iter().map(|r| format!("{:#06x}", r)).collect();
+            rows.push(Row {
+                offset: None,
+                bytes: &[],
+                text: format!("{}:", label.name),
+                comment: Some(format!("xref {}", refs.join(", "))),
+            });
         }
-        Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
-        Err(e) => panic!("{:?}", e),
     };
+    for line in &layout {
+        match *line {
+            Line::Instruction(offset, size) => {
+                emit_label(&mut rows, offset);
+                let code = &rom[offset..];
+                let (text, size) = if options.round_trip && !is_instruction(code) {
+                    (
+                        format!("dw   {:#06x}", u16::from_be_bytes([code[0], code[1]])),
+                        2,
+                    )
+                } else {
+                    (decompile_word_with_labels(code, &labels), size)
+                };
+                rows.push(Row {
+                    offset: Some(offset),
+                    bytes: &rom[offset..offset + size],
+                    text,
+                    comment: None,
+                });
+            }
+            Line::Data(offset, len) => {
+                // split data into rows, starting a new row wherever a label points, and giving
+                // each row of a sprite a row of its own so it can be drawn alongside
+                let mut start = offset;
+                while start < offset + len {
+                    emit_label(&mut rows, start);
+                    let end = if sprite[start] {
+                        start + 1
+                    } else {
+                        (start + 1..offset + len)
+                            .find(|&i| {
+                                i - start == 8
+                                    || sprite[i]
+                                    || address_of(i)
+                                        .is_some_and(|address| labels.contains_key(&address))
+                            })
+                            .unwrap_or(offset + len)
+                    };
+                    rows.push(Row {
+                        offset: Some(start),
+                        bytes: &rom[start..end],
+                        text: decompile_data(&rom[start..end]),
+                        comment: if sprite[start] {
+                            Some(sprite_row(rom[start]))
+                        } else {
+                            None
+                        },
+                    });
+                    start = end;
+                }
+            }
+        }
+    }
 
+    let decomp: Vec<String> = rows
+        .iter()
+        .map(|row| format_row(row, options.listing))
+        .collect();
     decomp.join("\n")
 }
 
-pub fn decompile_word(upper: u8, lower: u8) -> String {
-    let n1 = (upper & 0b1111_0000) >> 4;
-    let n2 = upper & 0b0000_1111;
-    let n3 = (lower & 0b1111_0000) >> 4;
-    let n4 = lower & 0b0000_1111;
-    let decomp = match (n1, n2, n3, n4) {
-        (0x0, 0x0, 0xe, 0x0) | (0x0, 0x0, 0xe, 0xe) => decompile_NNNN(n1, n2, n3, n4),
-        (0x1, _, _, _) | (0x2, _, _, _) | (0xa, _, _, _) | (0x_b, _, _, _) => {
-            decompile_Nnnn(n1, n2, n3, n4)
-        }
-        (0x3, _, _, _) | (0x4, _, _, _) | (0x6, _, _, _) | (0x7, _, _, _) | (0xc, _, _, _) => {
-            decompile_Nxkk(n1, n2, n3, n4)
-        }
-        (0x5, _, _, 0x0)
-        | (0x8, _, _, 0x0)
-        | (0x8, _, _, 0x1)
-        | (0x8, _, _, 0x2)
-        | (0x8, _, _, 0x3)
-        | (0x8, _, _, 0x4)
-        | (0x8, _, _, 0x5)
-        | (0x8, _, _, 0x6)
-        | (0x8, _, _, 0x7)
-        | (0x8, _, _, 0xe)
-        | (0x9, _, _, 0x0) => decompile_NxyN(n1, n2, n3, n4),
-        (0xd, _, _, _) => decompile_Nxyn(n1, n2, n3, n4),
-        (0xe, _, 0x9, 0xe)
-        | (0xe, _, 0xa, 0x1)
-        | (0xf, _, 0x0, 0x7)
-        | (0xf, _, 0x0, 0xa)
-        | (0xf, _, 0x1, 0x5)
-        | (0xf, _, 0x1, 0x8)
-        | (0xf, _, 0x1, 0xe)
-        | (0xf, _, 0x2, 0x9)
-        | (0xf, _, 0x3, 0x3)
-        | (0xf, _, 0x5, 0x5)
-        | (0xf, _, 0x6, 0x5) => decompile_NxNN(n1, n2, n3, n4),
-        (_, _, _, _) => {
-            let word: u16 = ((upper as u16) << 8) | lower as u16;
-            format!("{:#06x}", word,)
+fn format_row(row: &Row, listing: bool) -> String {
+    let mut line = String::new();
+    if listing {
+        // address and raw bytes, lining up with the pc that the debugger shows in regs and disasm
+        let raw: String = row
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        match row.offset {
+            Some(offset) => {
+                line += &format!("{:#06x}  {:<16}  ", RAM_OFFSET as usize + offset, raw)
+            }
+            None => line += &" ".repeat(26),
         }
-    };
-    decomp
+    }
+    match &row.comment {
+        Some(comment) => line += &format!("{:<20} ; {}", row.text, comment),
+        None => line += &row.text,
+    }
+    line
 }
 
-#[allow(non_snake_case)]
-fn decompile_Nnnn(n1: u8, n2: u8, n3: u8, n4: u8) -> String {
-    let instruction = match n1 {
-        0x1 => "JP  ",
-        0x2 => "CALL",
-        0xa => "LD   I,  ",
-        0xb => "JP   V0, ",
-        _ => "Unrecognized",
-    };
-    let address = ((n2 as u16) << 8) | ((n3 as u16) << 4) | n4 as u16;
-    if instruction != "Unrecognized" {
-        format!("{} {:#06x}", instruction, address)
-    } else {
-        instruction.to_owned()
+// reachable instructions, with everything in between them as data
+fn layout_code(rom: &[u8], code: &[bool]) -> Vec<Line> {
+    let mut layout = Vec::new();
+    let mut offset = 0;
+    while offset < code.len() {
+        if code[offset] {
+            let size = instruction_size(rom, offset);
+            layout.push(Line::Instruction(offset, size));
+            offset += size;
+        } else {
+            let end = (offset..code.len())
+                .find(|&i| code[i])
+                .unwrap_or(code.len());
+            layout.push(Line::Data(offset, end - offset));
+            offset = end;
+        }
     }
+    layout
 }
 
-#[allow(non_snake_case)]
-fn decompile_Nxkk(n1: u8, n2: u8, n3: u8, n4: u8) -> String {
-    let instruction = match n1 {
-        0x3 => "SE  ",
-        0x4 => "SNE ",
-        0x6 => "LD  ",
-        0x7 => "ADD ",
-        0xC => "RND ",
-        _ => "Unrecognized",
-    };
-    let register = n2;
-    let byte = (n3 << 4) | n4;
-    if instruction != "Unrecognized" {
-        format!("{} v{:01X?},  {:#04x}", instruction, register, byte)
-    } else {
-        instruction.to_owned()
+// every instruction in order
+fn layout_linear(rom: &[u8]) -> Vec<Line> {
+    let mut layout = Vec::new();
+    let mut offset = 0;
+    while offset + 1 < rom.len() {
+        let size = instruction_size(rom, offset);
+        layout.push(Line::Instruction(offset, size));
+        offset += size;
+    }
+    // a ROM with an odd length leaves a single byte at the end
+    if offset < rom.len() {
+        layout.push(Line::Data(offset, 1));
     }
+    layout
+}
+
+// where the byte at `offset` in the ROM is loaded, or None if that's past the end of memory
+fn address_of(offset: usize) -> Option<u16> {
+    u16::try_from(offset).ok()?.checked_add(RAM_OFFSET)
 }
 
-#[allow(non_snake_case)]
-fn decompile_NNNN(n1: u8, n2: u8, n3: u8, n4: u8) -> String {
-    match (n1, n2, n3, n4) {
-        (0x0, 0x0, 0xe, 0x0) => "CLS".to_string(),
-        (0x0, 0x0, 0xe, 0xe) => "RET".to_string(),
-        _ => "Unrecognized".to_string(),
+// a LD I, LONG cut short by the end of the ROM is just a word that can't be decoded
+fn instruction_size(rom: &[u8], offset: usize) -> usize {
+    match size_at(&rom[offset..]) {
+        4 if offset + 4 > rom.len() => 2,
+        size => size,
     }
 }
 
-#[allow(non_snake_case)]
-fn decompile_NxyN(n1: u8, n2: u8, n3: u8, n4: u8) -> String {
-    let instruction = match (n1, n4) {
-        (0x5, 0x0) => "SE  ",
-        (0x8, 0x0) => "LD  ",
-        (0x8, 0x1) => "OR  ",
-        (0x8, 0x2) => "AND ",
-        (0x8, 0x3) => "XOR ",
-        (0x8, 0x4) => "ADD ",
-        (0x8, 0x5) => "SUB ",
-        (0x8, 0x6) => "SHR ",
-        (0x8, 0x7) => "SUBN",
-        (0x8, 0xe) => "SHL ",
-        (0x9, 0x0) => "SNE ",
-        _ => "Unrecognized",
-    };
-    let r1 = n2;
-    let r2 = n3;
-    if instruction != "Unrecognized" {
-        format!("{} v{:01X?},  v{:01X?}", instruction, r1, r2)
-    } else {
-        instruction.to_owned()
+// name every address that an instruction refers to, as long as a label can be placed there
+fn find_labels(rom: &[u8], layout: &[Line]) -> BTreeMap<u16, Label> {
+    let mut placeable = vec![false; rom.len()];
+    let mut refs: BTreeMap<u16, Vec<(analysis::Reference, u16)>> = BTreeMap::new();
+    for line in layout {
+        match *line {
+            Line::Instruction(offset, _) => {
+                placeable[offset] = true;
+                if !is_instruction(&rom[offset..]) {
+                    continue;
+                }
+                let reference = analysis::reference(&rom[offset..]);
+                if let (Some((kind, target)), Some(from)) = (reference, address_of(offset)) {
+                    refs.entry(target).or_default().push((kind, from));
+                }
+            }
+            Line::Data(offset, len) => placeable[offset..offset + len].fill(true),
+        }
+    }
+
+    let mut labels = BTreeMap::new();
+    for (target, refs) in refs {
+        let in_rom = target
+            .checked_sub(RAM_OFFSET)
+            .is_some_and(|offset| placeable.get(offset as usize) == Some(&true));
+        if !in_rom {
+            continue;
+        }
+        let prefix = match refs.iter().map(|&(kind, _)| kind).max() {
+            Some(analysis::Reference::Call) => "sub",
+            Some(analysis::Reference::Jump) if refs.iter().any(|&(_, from)| from >= target) => {
+                "loop"
+            }
+            Some(analysis::Reference::Jump) => "label",
+            Some(analysis::Reference::Table) => "table",
+            _ => "data",
+        };
+        labels.insert(
+            target,
+            Label {
+                name: format!("{}_{:03X}", prefix, target),
+                refs: refs.iter().map(|&(_, from)| from).collect(),
+            },
+        );
     }
+    labels
 }
 
-#[allow(non_snake_case)]
-fn decompile_Nxyn(n1: u8, n2: u8, n3: u8, n4: u8) -> String {
-    let instruction = match n1 {
-        0xD => "DRW ",
-        _ => "Unrecognized",
-    };
-    let r1 = n2;
-    let r2 = n3;
-    if instruction != "Unrecognized" {
-        format!("{} v{:01X?},  v{:01X?}, {:#03x}", instruction, r1, r2, n4)
-    } else {
-        instruction.to_owned()
+fn decompile_word_with_labels(code: &[u8], labels: &BTreeMap<u16, Label>) -> String {
+    let text = decompile_word(code);
+    match analysis::reference(code).and_then(|(_, target)| labels.get(&target).map(|l| (target, l)))
+    {
+        Some((target, label)) => text.replace(&format!("{:#06x}", target), &label.name),
+        None => text,
     }
 }
 
-#[allow(non_snake_case)]
-fn decompile_NxNN(n1: u8, n2: u8, n3: u8, n4: u8) -> String {
-    let r1 = n2;
-    let instruction = match (n1, n3, n4) {
-        (0xe, 0x9, 0xe) => format!("SKP  v{:01X?}", r1),
-        (0xe, 0xa, 0x1) => format!("SKNP v{:01X?}", r1),
-        (0xf, 0x0, 0x7) => format!("LD   v{:01X?},  DT", r1),
-        (0xf, 0x0, 0xa) => format!("LD   v{:01X?},  K", r1),
-        (0xf, 0x1, 0x5) => format!("LD   DT,  v{:01X?}", r1),
-        (0xf, 0x1, 0x8) => format!("LD   ST,  v{:01X?}", r1),
-        (0xf, 0x1, 0xe) => format!("ADD  I,   v{:01X?}", r1),
-        (0xf, 0x2, 0x9) => format!("LD   F,   v{:01X?}", r1),
-        (0xf, 0x3, 0x3) => format!("LD   B,   v{:01X?}", r1),
-        (0xf, 0x5, 0x5) => format!("LD   [I], v{:01X?}", r1),
-        (0xf, 0x6, 0x5) => format!("LD   v{:01X?},  [I]", r1),
-        _ => "Unrecognized".to_string(),
-    };
-    instruction
+fn sprite_row(byte: u8) -> String {
+    (0..8)
+        .map(|x| {
+            if byte & (0b1000_0000 >> x) != 0 {
+                '#'
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+fn decompile_data(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:#04x}", byte)).collect();
+    format!("db   {}", bytes.join(", "))
+}
+
+fn is_instruction(code: &[u8]) -> bool {
+    decode_at(code).is_ok()
+}
+
+// decompile the instruction at the start of `code`, which is only longer than a word for a LD I,
+// LONG. Anything that can't be decoded comes out as the hexadecimal word, or as data if there's
+// less than a word of it
+pub fn decompile_word(code: &[u8]) -> String {
+    match (decode_at(code), code) {
+        (Ok(instruction), _) => instruction.to_string(),
+        (Err(_), [high, low, ..]) => format!("{:#06x}", u16::from_be_bytes([*high, *low])),
+        (Err(_), _) => decompile_data(code),
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::assembler::assemble;
     use super::*;
 
-    #[allow(non_snake_case)]
+    use std::fs;
+
     #[test]
-    fn test_decompile_Nnnn() {
-        assert_eq!(decompile_Nnnn(0x1, 0x2, 0x3, 0x4), "JP   0x0234".to_owned());
-        assert_eq!(decompile_Nnnn(0x2, 0x2, 0x3, 0x4), "CALL 0x0234".to_owned());
-        assert_eq!(
-            decompile_Nnnn(0xa, 0x2, 0x3, 0x4),
-            "LD   I,   0x0234".to_owned()
-        );
-        assert_eq!(
-            decompile_Nnnn(0xb, 0x2, 0x3, 0x4),
-            "JP   V0,  0x0234".to_owned()
-        );
-        assert_eq!(decompile_Nxkk(0xA, 0x4, 0x5, 0x6), "Unrecognized");
+    fn test_decompile_addresses() {
+        assert_eq!(decompile_word(&[0x12, 0x34]), "JP   0x0234".to_owned());
+        assert_eq!(decompile_word(&[0x22, 0x34]), "CALL 0x0234".to_owned());
+        assert_eq!(decompile_word(&[0xa2, 0x34]), "LD   I,   0x0234".to_owned());
+        assert_eq!(decompile_word(&[0xb2, 0x34]), "JP   V0,  0x0234".to_owned());
+    }
+
+    #[test]
+    fn test_decompile_byte_literals() {
+        assert_eq!(decompile_word(&[0x34, 0x56]), "SE   v4,  0x56");
+        assert_eq!(decompile_word(&[0x44, 0x56]), "SNE  v4,  0x56");
+        assert_eq!(decompile_word(&[0x64, 0x56]), "LD   v4,  0x56");
+        assert_eq!(decompile_word(&[0x74, 0x56]), "ADD  v4,  0x56");
+        assert_eq!(decompile_word(&[0xc4, 0x56]), "RND  v4,  0x56");
     }
 
-    #[allow(non_snake_case)]
     #[test]
-    fn test_decompile_Nxkk() {
-        assert_eq!(decompile_Nxkk(0x3, 0x4, 0x5, 0x6), "SE   v4,  0x56");
-        assert_eq!(decompile_Nxkk(0x4, 0x4, 0x5, 0x6), "SNE  v4,  0x56");
-        assert_eq!(decompile_Nxkk(0x6, 0x4, 0x5, 0x6), "LD   v4,  0x56");
-        assert_eq!(decompile_Nxkk(0x7, 0x4, 0x5, 0x6), "ADD  v4,  0x56");
-        assert_eq!(decompile_Nxkk(0xC, 0x4, 0x5, 0x6), "RND  v4,  0x56");
-        assert_eq!(decompile_Nxkk(0xA, 0x4, 0x5, 0x6), "Unrecognized");
+    fn test_decompile_no_operands() {
+        assert_eq!(decompile_word(&[0x00, 0xe0]), "CLS");
+        assert_eq!(decompile_word(&[0x00, 0xee]), "RET");
+        assert_eq!(decompile_word(&[0x00, 0xfb]), "SCR");
+        assert_eq!(decompile_word(&[0x00, 0xfc]), "SCL");
+        assert_eq!(decompile_word(&[0x00, 0xfd]), "EXIT");
+        assert_eq!(decompile_word(&[0x00, 0xfe]), "LOW");
+        assert_eq!(decompile_word(&[0x00, 0xff]), "HIGH");
+        assert_eq!(decompile_word(&[0x00, 0xc3]), "SCD  0x3");
     }
 
-    #[allow(non_snake_case)]
     #[test]
-    fn test_decompile_NNNN() {
-        assert_eq!(decompile_NNNN(0x0, 0x0, 0xE, 0x0), "CLS");
-        assert_eq!(decompile_NNNN(0x0, 0x0, 0xE, 0xE), "RET");
-        assert_eq!(decompile_Nxkk(0xA, 0x4, 0x5, 0x6), "Unrecognized");
+    fn test_decompile_registers() {
+        assert_eq!(decompile_word(&[0x50, 0xe0]), "SE   v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xe0]), "LD   v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xe1]), "OR   v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xe2]), "AND  v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xe3]), "XOR  v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xe4]), "ADD  v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xe5]), "SUB  v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xe6]), "SHR  v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xe7]), "SUBN v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xee]), "SHL  v0,  vE");
+        assert_eq!(decompile_word(&[0x90, 0xe0]), "SNE  v0,  vE");
+        assert_eq!(decompile_word(&[0x80, 0xef]), "0x80ef");
     }
 
-    #[allow(non_snake_case)]
     #[test]
-    fn test_decompile_NxyN() {
-        assert_eq!(decompile_NxyN(0x5, 0x0, 0xE, 0x0), "SE   v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0x0), "LD   v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0x1), "OR   v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0x2), "AND  v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0x3), "XOR  v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0x4), "ADD  v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0x5), "SUB  v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0x6), "SHR  v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0x7), "SUBN v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0xE), "SHL  v0,  vE");
-        assert_eq!(decompile_NxyN(0x9, 0x0, 0xE, 0x0), "SNE  v0,  vE");
-        assert_eq!(decompile_NxyN(0x8, 0x0, 0xE, 0xF), "Unrecognized");
+    fn test_decompile_drw() {
+        assert_eq!(decompile_word(&[0xd0, 0xe3]), "DRW  v0,  vE, 0x3");
+        assert_eq!(decompile_word(&[0xe0, 0xef]), "0xe0ef");
     }
 
-    #[allow(non_snake_case)]
     #[test]
-    fn test_decompile_Nxyn() {
-        assert_eq!(decompile_Nxyn(0xD, 0x0, 0xE, 0x3), "DRW  v0,  vE, 0x3");
-        assert_eq!(decompile_NxyN(0xE, 0x0, 0xE, 0xF), "Unrecognized");
+    fn test_decompile_single_register() {
+        assert_eq!(decompile_word(&[0xe2, 0x9e]), "SKP  v2".to_owned());
+        assert_eq!(decompile_word(&[0xea, 0xa1]), "SKNP vA".to_owned());
+        assert_eq!(decompile_word(&[0xf8, 0x07]), "LD   v8,  DT".to_owned());
+        assert_eq!(decompile_word(&[0xf7, 0x0a]), "LD   v7,  K".to_owned());
+        assert_eq!(decompile_word(&[0xf0, 0x15]), "LD   DT,  v0".to_owned());
+        assert_eq!(decompile_word(&[0xf4, 0x18]), "LD   ST,  v4".to_owned());
+        assert_eq!(decompile_word(&[0xf2, 0x1e]), "ADD  I,   v2".to_owned());
+        assert_eq!(decompile_word(&[0xf2, 0x29]), "LD   F,   v2".to_owned());
+        assert_eq!(decompile_word(&[0xf2, 0x33]), "LD   B,   v2".to_owned());
+        assert_eq!(decompile_word(&[0xf2, 0x55]), "LD   [I], v2".to_owned());
+        assert_eq!(decompile_word(&[0xf2, 0x65]), "LD   v2,  [I]".to_owned());
+        assert_eq!(decompile_word(&[0xf2, 0x30]), "LD   HF,  v2".to_owned());
+        assert_eq!(decompile_word(&[0xf2, 0x75]), "LD   R,   v2".to_owned());
+        assert_eq!(decompile_word(&[0xf2, 0x85]), "LD   v2,  R".to_owned());
+        assert_eq!(decompile_word(&[0xf0, 0x66]), "0xf066");
     }
 
-    #[allow(non_snake_case)]
     #[test]
-    fn test_decompile_NxNN() {
-        assert_eq!(decompile_NxNN(0xE, 0x2, 0x9, 0xE), "SKP  v2".to_owned());
-        assert_eq!(decompile_NxNN(0xE, 0xA, 0xA, 0x1), "SKNP vA".to_owned());
+    fn test_decompile_xo_chip() {
         assert_eq!(
-            decompile_NxNN(0xF, 0x8, 0x0, 0x7),
-            "LD   v8,  DT".to_owned()
+            decompile_word(&[0xf0, 0x00, 0x12, 0x34]),
+            "LD   I,   LONG 0x1234"
         );
-        assert_eq!(decompile_NxNN(0xF, 0x7, 0x0, 0xA), "LD   v7,  K".to_owned());
+        assert_eq!(decompile_word(&[0xf0, 0x00]), "0xf000");
+        assert_eq!(decompile_word(&[0xf0]), "db   0xf0");
+        assert_eq!(decompile_word(&[0x51, 0x32]), "LD   [I], v1-v3");
+        assert_eq!(decompile_word(&[0x53, 0x13]), "LD   v3-v1, [I]");
+        assert_eq!(decompile_word(&[0xf3, 0x01]), "PLANE 0x3");
+        assert_eq!(decompile_word(&[0xf0, 0x02]), "AUDIO");
+        assert_eq!(decompile_word(&[0xf2, 0x3a]), "LD   PITCH, v2");
+        assert_eq!(decompile_word(&[0x00, 0xd4]), "SCU  0x4");
+
+        let rom = [0xf0, 0x00, 0x02, 0x06, 0x12, 0x04, 0xff, 0xf0, 0x00];
         assert_eq!(
-            decompile_NxNN(0xF, 0x0, 0x1, 0x5),
-            "LD   DT,  v0".to_owned()
+            decompile_bytes(&rom, &DecompileOptions::default()),
+            "LD   I,   LONG data_206\nloop_204:            ; xref 0x0204\nJP   loop_204\ndata_206:            ; xref 0x0200\ndb   0xff, 0xf0, 0x00"
         );
+        let options = DecompileOptions {
+            linear_sweep: true,
+            round_trip: true,
+            ..DecompileOptions::default()
+        };
+        let source = decompile_bytes(&rom, &options);
+        assert!(source.starts_with("LD   I,   LONG data_206\n"));
+        assert_eq!(assemble(&source, None), Ok(rom.to_vec()));
+    }
+
+    #[test]
+    fn test_decompile_linear() {
+        let rom = [0x00, 0xe0, 0x01, 0x23, 0xab];
+        let options = DecompileOptions {
+            linear_sweep: true,
+            ..DecompileOptions::default()
+        };
+        assert_eq!(decompile_bytes(&rom, &options), "CLS\n0x0123\ndb   0xab");
+        let options = DecompileOptions {
+            round_trip: true,
+            linear_sweep: true,
+            ..DecompileOptions::default()
+        };
         assert_eq!(
-            decompile_NxNN(0xF, 0x4, 0x1, 0x8),
-            "LD   ST,  v4".to_owned()
+            decompile_bytes(&rom, &options),
+            "CLS\ndw   0x0123\ndb   0xab"
         );
         assert_eq!(
-            decompile_NxNN(0xF, 0x2, 0x1, 0xE),
-            "ADD  I,   v2".to_owned()
+            assemble(&decompile_bytes(&rom, &options), None),
+            Ok(rom.to_vec())
         );
+    }
+
+    #[test]
+    fn test_read_rom() {
+        let path = std::env::temp_dir().join("chip8_empty_test.ch8");
+        fs::write(&path, []).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        assert!(matches!(read_rom(path), Err(Chip8Error::EmptyRom)));
+        assert!(matches!(
+            read_rom("missing.ch8".to_string()),
+            Err(Chip8Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_decompile_oversized() {
+        let path = std::env::temp_dir().join("chip8_oversized_test.ch8");
+        fs::write(&path, vec![0x12; 70_000]).unwrap();
+        let path = path.to_string_lossy().into_owned();
+        assert!(matches!(
+            decompile_rom(path, &DecompileOptions::default()),
+            Err(Chip8Error::RomTooLarge(70_000))
+        ));
+        // and the bytes on their own, past the end of memory, get no labels
+        let options = DecompileOptions {
+            linear_sweep: true,
+            listing: true,
+            ..DecompileOptions::default()
+        };
+        let source = decompile_bytes(&vec![0x12; 70_000], &options);
         assert_eq!(
-            decompile_NxNN(0xF, 0x2, 0x2, 0x9),
-            "LD   F,   v2".to_owned()
+            source.lines().last(),
+            Some("0x1136e  1212              JP   loop_212")
         );
+    }
+
+    #[test]
+    fn test_decompile_truncated_long() {
+        let options = DecompileOptions {
+            linear_sweep: true,
+            ..DecompileOptions::default()
+        };
+        assert_eq!(decompile_bytes(&[0xf0, 0x00], &options), "0xf000");
         assert_eq!(
-            decompile_NxNN(0xF, 0x2, 0x3, 0x3),
-            "LD   B,   v2".to_owned()
+            decompile_bytes(&[0x00, 0xe0, 0xf0, 0x00], &options),
+            "CLS\n0xf000"
         );
+        let options = DecompileOptions {
+            listing: true,
+            ..options
+        };
         assert_eq!(
-            decompile_NxNN(0xF, 0x2, 0x5, 0x5),
-            "LD   [I], v2".to_owned()
+            decompile_bytes(&[0x00, 0xe0, 0xf0, 0x00], &options),
+            "0x0200  00E0              CLS\n0x0202  F000              0xf000"
         );
+    }
+
+    #[test]
+    fn test_decompile_bytes() {
+        let rom = [
+            0xa2, 0x06, // LD   I,   0x0206
+            0xd0, 0x13, // DRW  v0,  v1, 0x3
+            0x12, 0x04, // JP   0x0204
+            0xf0, 0x90, 0xf0, // sprite
+        ];
+        let expected = [
+            "LD   I,   data_206",
+            "DRW  v0,  v1, 0x3",
+            "loop_204:            ; xref 0x0204",
+            "JP   loop_204",
+            "data_206:            ; xref 0x0200",
+            "db   0xf0            ; ####....",
+            "db   0x90            ; #..#....",
+            "db   0xf0            ; ####....",
+        ];
         assert_eq!(
-            decompile_NxNN(0xF, 0x2, 0x6, 0x5),
-            "LD   v2,  [I]".to_owned()
+            decompile_bytes(&rom, &DecompileOptions::default()),
+            expected.join("\n")
         );
-        assert_eq!(decompile_NxyN(0xF, 0x0, 0x6, 0x6), "Unrecognized");
+    }
+
+    #[test]
+    fn test_decompile_sprites() {
+        let rom = [
+            0xa2, 0x06, // LD   I,   0x0206
+            0xd0, 0x12, // DRW  v0,  v1, 0x2
+            0x12, 0x04, // JP   0x0204
+            0x3c, 0xc3, // sprite
+            0x01, 0x02, 0x03, // other data
+        ];
+        let expected = [
+            "LD   I,   data_206",
+            "DRW  v0,  v1, 0x2",
+            "loop_204:            ; xref 0x0204",
+            "JP   loop_204",
+            "data_206:            ; xref 0x0200",
+            "db   0x3c            ; ..####..",
+            "db   0xc3            ; ##....##",
+            "db   0x01, 0x02, 0x03",
+        ];
+        assert_eq!(
+            decompile_bytes(&rom, &DecompileOptions::default()),
+            expected.join("\n")
+        );
+    }
+
+    #[test]
+    fn test_decompile_listing() {
+        let rom = [
+            0x22, 0x04, // CALL 0x0204
+            0x12, 0x02, // JP   0x0202
+            0x00, 0xee, // RET
+            0x01, 0x02, 0x03,
+        ];
+        let options = DecompileOptions {
+            listing: true,
+            ..DecompileOptions::default()
+        };
+        let expected = [
+            "0x0200  2204              CALL sub_204",
+            "                          loop_202:            ; xref 0x0202",
+            "0x0202  1202              JP   loop_202",
+            "                          sub_204:             ; xref 0x0200",
+            "0x0204  00EE              RET",
+            "0x0206  010203            db   0x01, 0x02, 0x03",
+        ];
+        assert_eq!(decompile_bytes(&rom, &options), expected.join("\n"));
+    }
+
+    #[test]
+    fn test_decompile_labels() {
+        let rom = [
+            0x22, 0x06, // CALL 0x0206
+            0x12, 0x08, // JP   0x0208
+            0xa2, 0x0b, // unreachable
+            0x00, 0xee, // RET
+            0xa2, 0x0b, // LD   I,   0x020b (in the middle of an instruction)
+            0x12, 0x01, // JP   0x0201 (also in the middle of an instruction)
+            0xff, 0xff,
+        ];
+        let expected = [
+            "CALL sub_206",
+            "JP   label_208",
+            "db   0xa2, 0x0b",
+            "sub_206:             ; xref 0x0200",
+            "RET",
+            "label_208:           ; xref 0x0202",
+            "LD   I,   0x020b",
+            "JP   0x0201",
+            "db   0xff, 0xff",
+        ];
+        assert_eq!(
+            decompile_bytes(&rom, &DecompileOptions::default()),
+            expected.join("\n")
+        );
+
+        let rom = [
+            0xa2, 0x03, 0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99,
+        ];
+        let options = DecompileOptions {
+            linear_sweep: true,
+            ..DecompileOptions::default()
+        };
+        assert_eq!(
+            decompile_bytes(&rom, &options),
+            "LD   I,   0x0203\n0x0011\nCALL 0x0233\nSNE  v4,  0x55\nLD   v6,  0x77\n0x8899"
+        );
+    }
+
+    #[test]
+    fn test_round_trip_roms() {
+        for linear_sweep in [false, true] {
+            let options = DecompileOptions {
+                round_trip: true,
+                linear_sweep,
+                ..DecompileOptions::default()
+            };
+            let roms = fs::read_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/Roms")).unwrap();
+            for rom in roms {
+                let path = rom.unwrap().path();
+                let rom = fs::read(&path).unwrap();
+                let source = decompile_bytes(&rom, &options);
+                assert_eq!(assemble(&source, None), Ok(rom), "{}", path.display());
+            }
+        }
     }
 }
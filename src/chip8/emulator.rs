@@ -1,76 +1,239 @@
-use super::decompiler;
-
-use super::constants::{CELL_H, CELL_W, CHIP8_DISP_H, CHIP8_DISP_W, DEBUG, FONT, FPS, RAM_OFFSET};
-
-use std::convert::TryInto;
-use std::fs::File;
-use std::io::Read;
-use std::io::{BufReader, ErrorKind};
-
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use std::time::Duration;
-
-type Address = u16;
-type Greg = u8;
-
-#[derive(Default)]
-struct Chip8Regs {
-    vx: [Greg; 16],
-    dt: u8,
-    st: u8,
-    i: Address,
-    pc: Address,
-    sp: i8,
+use super::breakpoint::{Access, Breakpoint, Hit, Watchpoint};
+use super::frontend::Frontend;
+use super::instruction::{decode_at, size_at, Address, Instruction, Register};
+use super::quirks::{LoadStore, Quirks};
+use super::rewind::{History, Record, Vram};
+use super::scheduler::Scheduler;
+
+use super::constants::{
+    BIG_FONT, BIG_FONT_OFFSET, CHIP8_DISP_H, CHIP8_DISP_W, FONT, FRAME_RATE,
+    INSTRUCTIONS_PER_FRAME, RAM_OFFSET, SCHIP_DISP_H, SCHIP_DISP_W,
+};
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::mem;
+use std::thread;
+use std::time::Instant;
+
+type Greg = Register;
+
+// XO-CHIP's 64K of RAM
+const RAM_SIZE: usize = 0x10000;
+// everything from the end of the interpreter's area up to the end of RAM
+pub(super) const MAX_ROM_SIZE: usize = RAM_SIZE - RAM_OFFSET as usize;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Chip8Regs {
+    pub vx: [Greg; 16],
+    pub dt: u8,
+    pub st: u8,
+    pub i: Address,
+    pub pc: Address,
+    // the top of the stack, or -1 when it's empty
+    pub sp: i8,
 }
 
 pub struct Chip8 {
     registers: Chip8Regs,
-    ram: [u8; 0x1000],
-    vram: [u64; CHIP8_DISP_H as usize],
+    ram: [u8; RAM_SIZE],
+    // XO-CHIP's two planes, each big enough for hi-res mode, of which lo-res only uses the top
+    // left corner
+    vram: Vram,
+    hires: bool,
+    // the planes that drawing, clearing and scrolling apply to, with bit p set for plane p
+    planes: u8,
     stack: [u16; 16],
+    // the HP48's RPL user flags, which LD R, vx and LD vx, R save registers to. The SUPER-CHIP has
+    // 8 of them and XO-CHIP 16
+    rpl: [u8; 16],
+    // XO-CHIP's 1-bit audio samples, played at a rate set by the pitch in place of the usual tone
+    // once AUDIO has loaded them
+    audio_pattern: Option<[u8; 16]>,
+    pitch: u8,
     keyboard: u16,
     draw_flag: bool,
     input_flag: bool,
+    instructions_per_frame: u32,
+    quirks: Quirks,
+    faults: FaultPolicy,
+    // the fault the machine stopped at, if it trapped on one
+    trap: Option<Chip8Error>,
+    // whether the ROM has stopped itself with EXIT
+    exited: bool,
+    breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
+    // the breakpoint or watchpoint the last instruction stopped at, if any
+    hit: Option<Hit>,
+    // what's needed to step back through execution, if it's being kept
+    history: Option<History>,
+    // the bytes the instruction being executed has written over, for its record in the history
+    written: Vec<(Address, u8)>,
 }
 
-impl Chip8 {
-    pub fn with_rom(path: String) -> Chip8 {
-        let mut chip8 = Chip8::new();
+// what to do when a ROM does something the hardware doesn't guard against
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FaultAction {
+    // stop with a Chip8Error
+    Error,
+    // stop executing but keep the machine as it was, so that the fault can be inspected
+    Trap,
+    // carry on like the real hardware: the stack pointer and memory addresses wrap around
+    Wrap,
+}
+
+impl std::str::FromStr for FaultAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(FaultAction::Error),
+            "trap" => Ok(FaultAction::Trap),
+            "wrap" => Ok(FaultAction::Wrap),
+            _ => Err(format!("unknown fault action `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FaultPolicy {
+    pub stack_overflow: FaultAction,
+    pub stack_underflow: FaultAction,
+    pub memory: FaultAction,
+}
 
-        let file = File::open(path).expect("Cannot Read ROM");
-        let mut buf = BufReader::new(file);
-        let mut rom_bytes = [0; (0x1000 - RAM_OFFSET as usize)];
+impl FaultPolicy {
+    pub fn all(action: FaultAction) -> FaultPolicy {
+        FaultPolicy {
+            stack_overflow: action,
+            stack_underflow: action,
+            memory: action,
+        }
+    }
+
+    fn action(&self, error: &Chip8Error) -> FaultAction {
+        match error {
+            Chip8Error::StackOverflow { .. } => self.stack_overflow,
+            Chip8Error::StackUnderflow { .. } => self.stack_underflow,
+            Chip8Error::MemoryOutOfBounds { .. } => self.memory,
+            _ => FaultAction::Error,
+        }
+    }
+}
 
-        match buf.read(&mut rom_bytes) {
-            Ok(0) => (println! {"No bytes read from ROM!"}),
-            Ok(n) => {
-                chip8.load_rom(&rom_bytes, n);
+impl Default for FaultPolicy {
+    fn default() -> Self {
+        FaultPolicy::all(FaultAction::Error)
+    }
+}
+
+#[derive(Debug)]
+pub enum Chip8Error {
+    Io(io::Error),
+    RomTooLarge(usize),
+    EmptyRom,
+    // the address is always that of the instruction at fault
+    InvalidOpcode { address: Address, opcode: u16 },
+    StackOverflow { address: Address },
+    StackUnderflow { address: Address },
+    MemoryOutOfBounds { address: Address, target: usize },
+    // a stack pointer set from outside that's not within the stack
+    InvalidStackPointer(i8),
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::Io(e) => write!(f, "cannot read ROM: {}", e),
+            Chip8Error::RomTooLarge(size) => write!(
+                f,
+                "ROM is {} bytes, but only {} fit in memory",
+                size, MAX_ROM_SIZE
+            ),
+            Chip8Error::EmptyRom => write!(f, "ROM is empty"),
+            Chip8Error::InvalidOpcode { address, opcode } => {
+                write!(f, "invalid opcode {:#06x} at {:#06x}", opcode, address)
             }
-            Err(ref e) if e.kind() == ErrorKind::Interrupted => (),
-            Err(e) => panic!("{:?}", e),
-        };
+            Chip8Error::StackOverflow { address } => write!(
+                f,
+                "stack overflow at {:#06x}: subroutine calls nest more than 16 deep",
+                address
+            ),
+            Chip8Error::StackUnderflow { address } => write!(
+                f,
+                "stack underflow at {:#06x}: RET outside of a subroutine",
+                address
+            ),
+            Chip8Error::MemoryOutOfBounds { address, target } => write!(
+                f,
+                "instruction at {:#06x} accesses {:#x}, past the end of memory",
+                address, target
+            ),
+            Chip8Error::InvalidStackPointer(sp) => write!(
+                f,
+                "sp {} is outside the stack, which runs from -1 when it's empty to 15",
+                sp
+            ),
+        }
+    }
+}
 
-        chip8
+impl error::Error for Chip8Error {}
+
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Self {
+        Chip8Error::Io(e)
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Self {
+        Chip8::new()
+    }
+}
+
+impl Chip8 {
+    pub fn with_rom(path: String) -> Result<Chip8, Chip8Error> {
+        let mut chip8 = Chip8::new();
+        chip8.load_rom(&fs::read(path)?)?;
+        Ok(chip8)
     }
 
     pub fn new() -> Chip8 {
         let mut chip8 = Chip8 {
             registers: Chip8Regs::default(),
-            ram: [0u8; 0x1000],
-            vram: [0u64; CHIP8_DISP_H as usize],
+            ram: [0u8; RAM_SIZE],
+            vram: [[0u128; SCHIP_DISP_H as usize]; 2],
+            hires: false,
+            planes: 0b01,
             stack: [0u16; 16],
+            rpl: [0u8; 16],
+            audio_pattern: None,
+            pitch: 64,
             keyboard: 0x00,
             draw_flag: false,
             input_flag: false,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            quirks: Quirks::default(),
+            faults: FaultPolicy::default(),
+            trap: None,
+            exited: false,
+            breakpoints: Vec::new(),
+            watchpoints: Vec::new(),
+            hit: None,
+            history: None,
+            written: Vec::new(),
         };
 
         // load font into RAM
         for (i, font_byte) in FONT.iter().enumerate() {
             chip8.ram[i] = *font_byte;
         }
+        for (i, font_byte) in BIG_FONT.iter().enumerate() {
+            chip8.ram[BIG_FONT_OFFSET as usize + i] = *font_byte;
+        }
 
         // initialize pointers
         chip8.registers.sp = -1;
@@ -78,94 +241,465 @@ impl Chip8 {
         chip8
     }
 
-    pub fn load_rom(&mut self, rom_bytes: &[u8], rom_size: usize) {
-        for (i, rom_byte) in rom_bytes.iter().enumerate().take(rom_size) {
-            self.ram[RAM_OFFSET as usize + i] = *rom_byte;
+    pub fn load_rom(&mut self, rom_bytes: &[u8]) -> Result<(), Chip8Error> {
+        if rom_bytes.is_empty() {
+            return Err(Chip8Error::EmptyRom);
+        }
+        if rom_bytes.len() > MAX_ROM_SIZE {
+            return Err(Chip8Error::RomTooLarge(rom_bytes.len()));
         }
+        let start = RAM_OFFSET as usize;
+        self.ram[start..start + rom_bytes.len()].copy_from_slice(rom_bytes);
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+        Ok(())
     }
 
-    fn tick(&mut self) {
-        self.instruction_dispatch(
-            self.ram[self.registers.pc as usize],
-            self.ram[(self.registers.pc + 1) as usize],
-        );
+    // execute a single instruction. When it fails, nothing has changed and pc still points at it
+    // while the machine is trapped or has exited, nothing happens
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if self.trap.is_some() || self.exited {
+            return Ok(());
+        }
+        self.hit = None;
+        let before = self.registers.clone();
+        let record = match self.history {
+            Some(_) => Some((self.record(false), Box::new(self.vram))),
+            None => None,
+        };
+        let code = self.fetch(self.registers.pc);
+        let executed = self.instruction_dispatch(code);
+        match executed {
+            Err(e) if self.faults.action(&e) == FaultAction::Trap => {
+                self.trap = Some(e);
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+            Ok(()) => (),
+        }
 
-        self.registers.pc += 2;
-        if self.registers.pc >= 0x0fff {
-            self.registers.pc = 0x0200;
+        // running off the end of memory starts the program over
+        self.registers.pc = match self.registers.pc.overflowing_add(size_at(&code) as u16) {
+            (_, true) => RAM_OFFSET,
+            (pc, false) => pc,
+        };
+        if let Some((record, vram)) = record {
+            self.remember(record, Some(vram));
         }
+        self.check_watchpoints(&before);
+        Ok(())
     }
-    fn update_timers(&mut self) {
-        if self.registers.dt > 0 {
-            self.registers.dt -= 1;
+
+    // the state that an instruction or a tick of the timers is about to change, apart from memory
+    // and the display
+    fn record(&self, frame: bool) -> Record {
+        Record {
+            registers: self.registers.clone(),
+            keyboard: self.keyboard,
+            stack: self.stack,
+            ram: Vec::new(),
+            vram: None,
+            hires: self.hires,
+            planes: self.planes,
+            rpl: self.rpl,
+            audio_pattern: self.audio_pattern,
+            pitch: self.pitch,
+            draw_flag: self.draw_flag,
+            exited: self.exited,
+            frame,
         }
+    }
 
-        if self.registers.st > 0 {
-            self.registers.st -= 1;
+    // add a record to the history, with what was written over and the display as it was, if
+    // that changed
+    fn remember(&mut self, mut record: Record, vram: Option<Box<Vram>>) {
+        record.ram = mem::take(&mut self.written);
+        record.vram = vram.filter(|vram| **vram != self.vram);
+        if let Some(history) = &mut self.history {
+            history.push(record);
         }
     }
 
-    pub fn run(&mut self) {
-        let sdl_context = sdl2::init().unwrap();
-        let video_subsystem = sdl_context.video().unwrap();
+    // put the machine back the way a record found it
+    fn undo(&mut self, record: Record) {
+        self.registers = record.registers;
+        self.keyboard = record.keyboard;
+        self.stack = record.stack;
+        for (address, byte) in record.ram.into_iter().rev() {
+            self.ram[address as usize] = byte;
+        }
+        if let Some(vram) = record.vram {
+            self.vram = *vram;
+        }
+        self.hires = record.hires;
+        self.planes = record.planes;
+        self.rpl = record.rpl;
+        self.audio_pattern = record.audio_pattern;
+        self.pitch = record.pitch;
+        self.draw_flag = record.draw_flag;
+        self.exited = record.exited;
+        self.trap = None;
+        self.hit = None;
+    }
 
-        let window = video_subsystem
-            .window(
-                "Chip-8 Emulator",
-                CELL_W * (CHIP8_DISP_W),
-                CELL_H * (CHIP8_DISP_H),
-            )
-            .position_centered()
-            .build()
-            .unwrap();
+    // keep a history of up to `depth` instructions, in no more than about `budget` bytes, to step
+    // back through. A depth of 0 stops keeping one. Changes made through set_registers and
+    // memory_mut aren't recorded, so they stay when stepping back past them
+    pub fn set_rewind(&mut self, depth: usize, budget: usize) {
+        self.history = match depth {
+            0 => None,
+            _ => Some(History::new(depth, budget)),
+        };
+    }
 
-        let mut canvas = window.into_canvas().build().unwrap();
+    pub fn history(&self) -> Option<&History> {
+        self.history.as_ref()
+    }
 
-        let mut event_pump = sdl_context.event_pump().unwrap();
-        'running: loop {
-            self.tick();
+    // undo the last instruction, along with any ticks of the timers since. Returns false when
+    // there's no instruction left in the history to undo
+    pub fn step_back(&mut self) -> bool {
+        while let Some(record) = self.history.as_mut().and_then(History::pop) {
+            let frame = record.frame;
+            self.undo(record);
+            if !frame {
+                return true;
+            }
+        }
+        false
+    }
 
-            for event in event_pump.poll_iter() {
-                match event {
-                    Event::Quit { .. }
-                    | Event::KeyDown {
-                        keycode: Some(Keycode::Escape),
-                        ..
-                    } => break 'running,
-                    _ => self.handle_key(event),
+    // step back until a breakpoint is hit, or the history runs out. Returns whether it stopped at
+    // a breakpoint
+    pub fn reverse_continue(&mut self) -> bool {
+        while self.step_back() {
+            if self.breakpoints.iter().any(|b| b.hit(&self.registers)) {
+                self.hit = Some(Hit::Breakpoint(self.registers.pc));
+                return true;
+            }
+        }
+        false
+    }
+
+    // undo everything back to the start of the frame, or of the one before if the timers have
+    // just ticked. Returns whether there was anything to undo
+    pub fn rewind_frame(&mut self) -> bool {
+        let mut rewound = false;
+        while let Some(record) = self.history.as_mut().and_then(History::pop) {
+            self.undo(record);
+            rewound = true;
+            if matches!(&self.history, Some(history) if history.at_frame()) {
+                break;
+            }
+        }
+        rewound
+    }
+
+    // see whether the instruction just executed changed a watched register, or has brought the
+    // machine to a breakpoint. A watched memory access will already have been seen
+    fn check_watchpoints(&mut self, before: &Chip8Regs) {
+        if self.hit.is_some() {
+            return;
+        }
+        for watchpoint in &self.watchpoints {
+            if let Watchpoint::Register(reg) = *watchpoint {
+                let (old, new) = (reg.value(before), reg.value(&self.registers));
+                if old != new {
+                    self.hit = Some(Hit::Register {
+                        pc: before.pc,
+                        reg,
+                        old,
+                        new,
+                    });
+                    return;
                 }
             }
+        }
+        if self.breakpoints.iter().any(|b| b.hit(&self.registers)) {
+            self.hit = Some(Hit::Breakpoint(self.registers.pc));
+        }
+    }
+
+    // the 4 bytes at `address`, enough for any instruction, wrapped around the end of memory
+    pub fn fetch(&self, address: Address) -> [u8; 4] {
+        let mut code = [0u8; 4];
+        for (i, byte) in code.iter_mut().enumerate() {
+            *byte = self.ram[(address as usize + i) % self.ram.len()];
+        }
+        code
+    }
+
+    // execute a 60th of a second's worth of instructions, then count the timers down once
+    pub fn frame(&mut self) -> Result<(), Chip8Error> {
+        self.frame_until(|_| false).map(|_| ())
+    }
+
+    // like frame, but stop before the first instruction that `stop` is true for, or after one
+    // that hits a breakpoint or watchpoint, leaving the timers as they are. Returns whether it
+    // stopped
+    pub fn frame_until<P: FnMut(&Chip8) -> bool>(
+        &mut self,
+        mut stop: P,
+    ) -> Result<bool, Chip8Error> {
+        for _ in 0..self.instructions_per_frame {
+            if stop(self) {
+                return Ok(true);
+            }
+            self.step()?;
+            if self.hit.is_some() {
+                return Ok(true);
+            }
+        }
+        if self.trap.is_none() && !self.exited {
+            self.update_timers();
+        }
+        Ok(false)
+    }
+
+    pub fn set_instructions_per_frame(&mut self, instructions: u32) {
+        self.instructions_per_frame = instructions;
+    }
 
-            if self.draw_flag {
-                canvas.set_draw_color(Color::RGB(0, 0, 0));
-                canvas.clear();
-                canvas.set_draw_color(Color::RGB(255, 255, 255));
-
-                for x in 0..CHIP8_DISP_W {
-                    for y in 0..CHIP8_DISP_H {
-                        //println!("{}", vram[y as usize]);
-                        if self.get_vram_bit(x as usize, y as usize) {
-                            canvas
-                                .fill_rect(Rect::new(
-                                    (x * CELL_W).try_into().unwrap(),
-                                    (y * CELL_H).try_into().unwrap(),
-                                    CELL_W,
-                                    CELL_H,
-                                ))
-                                .unwrap();
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    pub fn set_fault_policy(&mut self, faults: FaultPolicy) {
+        self.faults = faults;
+    }
+
+    pub fn trap(&self) -> Option<&Chip8Error> {
+        self.trap.as_ref()
+    }
+
+    // carry on from a trap. Unless something has been changed, the instruction will just fault again
+    pub fn clear_trap(&mut self) {
+        self.trap = None;
+    }
+
+    pub fn exited(&self) -> bool {
+        self.exited
+    }
+
+    pub fn registers(&self) -> &Chip8Regs {
+        &self.registers
+    }
+
+    // replace all of the registers, as long as sp still points into the stack
+    pub fn set_registers(&mut self, registers: Chip8Regs) -> Result<(), Chip8Error> {
+        if !(-1..self.stack.len() as i8).contains(&registers.sp) {
+            return Err(Chip8Error::InvalidStackPointer(registers.sp));
+        }
+        self.registers = registers;
+        Ok(())
+    }
+
+    pub fn memory(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn memory_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    // set a breakpoint, replacing any other at the same address
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.remove_breakpoint(breakpoint.address);
+        self.breakpoints.push(breakpoint);
+        self.breakpoints
+            .sort_by_key(|breakpoint| breakpoint.address);
+    }
+
+    // returns whether there was a breakpoint at the address
+    pub fn remove_breakpoint(&mut self, address: Address) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints
+            .retain(|breakpoint| breakpoint.address != address);
+        self.breakpoints.len() != count
+    }
+
+    // in order of address
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    // remove the watchpoint at `index` in watchpoints()
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    // in the order they were added
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // the breakpoint or watchpoint that the last instruction stopped the machine at
+    pub fn hit(&self) -> Option<&Hit> {
+        self.hit.as_ref()
+    }
+
+    // the return addresses of the subroutines being run, innermost last
+    pub fn stack(&self) -> &[Address] {
+        &self.stack[..(self.registers.sp + 1) as usize]
+    }
+
+    // run until the front-end asks to quit, or the ROM faults or exits. A trap keeps the front-end
+    // going, so that the screen can be seen as it was, and is left in trap() for the caller
+    pub fn run_with<F: Frontend>(&mut self, frontend: &mut F) -> Result<(), Chip8Error> {
+        self.run_until(frontend, |_| false).map(|_| ())
+    }
+
+    // like run_with, but also stop before the first instruction that `stop` is true for, or at a
+    // breakpoint or watchpoint. Returns whether it stopped that way, rather than the front-end
+    // quitting or the ROM exiting. A breakpoint on the instruction about to run stops it straight
+    // away, unless that's the breakpoint it last stopped at
+    pub fn run_until<F: Frontend, P: FnMut(&Chip8) -> bool>(
+        &mut self,
+        frontend: &mut F,
+        mut stop: P,
+    ) -> Result<bool, Chip8Error> {
+        if self.hit.is_none() && self.breakpoints.iter().any(|b| b.hit(&self.registers)) {
+            self.hit = Some(Hit::Breakpoint(self.registers.pc));
+            return Ok(true);
+        }
+        let mut scheduler = Scheduler::new(FRAME_RATE, Instant::now());
+        let mut sounding = false;
+        // the XO-CHIP pattern the front-end was last given
+        let mut pattern = None;
+        let mut stopped = false;
+        while let Some(keys) = frontend.poll_input() {
+            if self.exited {
+                break;
+            }
+            self.set_keys(keys);
+            let rewinding = frontend.rewinding();
+
+            let frames = scheduler.frames_due(Instant::now());
+            for _ in 0..frames {
+                if rewinding {
+                    self.rewind_frame();
+                    continue;
+                }
+                match self.frame_until(&mut stop) {
+                    Ok(false) => (),
+                    Ok(true) => {
+                        stopped = true;
+                        break;
+                    }
+                    Err(e) => {
+                        if sounding {
+                            frontend.stop_tone();
                         }
+                        return Err(e);
                     }
                 }
+            }
 
-                canvas.present();
+            if frames > 0 && self.draw_flag() {
+                frontend.present(self.framebuffer());
+            }
+            if stopped {
+                break;
+            }
+            let current = self
+                .audio_pattern
+                .map(|audio_pattern| (audio_pattern, self.pitch));
+            if current != pattern {
+                pattern = current;
+                frontend.set_pattern(self.audio_pattern.as_ref(), self.pitch);
+            }
+            let sound = self.sound_active() && self.trap.is_none() && !rewinding;
+            if sound != sounding {
+                sounding = sound;
+                if sounding {
+                    frontend.start_tone();
+                } else {
+                    frontend.stop_tone();
+                }
             }
-            ::std::thread::sleep(Duration::from_nanos(1_000_000_000u64 / FPS));
-            self.update_timers(); // TODO make this relaint on FPS, not just ticking down by 1 every cycle
+
+            thread::sleep(scheduler.until_next_frame());
         }
+        if sounding {
+            frontend.stop_tone();
+        }
+        Ok(stopped)
     }
 
+    pub fn update_timers(&mut self) {
+        if self.history.is_some() {
+            self.remember(self.record(true), None);
+        }
+        if self.registers.dt > 0 {
+            self.registers.dt -= 1;
+        }
+
+        if self.registers.st > 0 {
+            self.registers.st -= 1;
+        }
+    }
+
+    // the two planes of the display, each one u128 per row with the pixel in column x at bit x.
+    // There are 32 rows of 64 pixels, or 64 rows of 128 in hi-res mode
+    pub fn framebuffer(&self) -> [&[u128]; 2] {
+        let height = self.resolution().1 as usize;
+        [&self.vram[0][..height], &self.vram[1][..height]]
+    }
+
+    pub fn hires(&self) -> bool {
+        self.hires
+    }
+
+    // the width and height of the display in the current mode
+    fn resolution(&self) -> (u32, u32) {
+        if self.hires {
+            (SCHIP_DISP_W, SCHIP_DISP_H)
+        } else {
+            (CHIP8_DISP_W, CHIP8_DISP_H)
+        }
+    }
+
+    // the bits of a row that are on the display in the current mode
+    fn row_mask(&self) -> u128 {
+        u128::MAX >> (128 - self.resolution().0)
+    }
+
+    // the planes that drawing, clearing and scrolling apply to
+    fn selected_planes(&self) -> impl Iterator<Item = usize> {
+        let planes = self.planes;
+        (0..2).filter(move |plane| planes & (1 << plane) != 0)
+    }
+
+    // whether anything has been drawn since the display was created
+    pub fn draw_flag(&self) -> bool {
+        self.draw_flag
+    }
+
+    pub fn sound_active(&self) -> bool {
+        self.registers.st > 0
+    }
+
+    // the pattern AUDIO last loaded, if any
+    pub fn audio_pattern(&self) -> Option<&[u8; 16]> {
+        self.audio_pattern.as_ref()
+    }
+
+    pub fn pitch(&self) -> u8 {
+        self.pitch
+    }
+
+    // whether the pixel is set in the first plane
     pub fn get_vram_bit(&self, x: usize, y: usize) -> bool {
-        self.vram[y] & (1 << x) == (1 << x)
+        self.vram[0][y] & (1 << x) == (1 << x)
     }
 
     pub fn keydown(&mut self, key: u16) {
@@ -176,51 +710,150 @@ impl Chip8 {
         self.keyboard &= !(key);
     }
 
+    // replace the state of the whole keypad, with key n held down when bit n is set
+    pub fn set_keys(&mut self, keys: u16) {
+        self.keyboard = keys;
+    }
+
+    // the keys held down, as in set_keys
+    pub fn keys(&self) -> u16 {
+        self.keyboard
+    }
+
     /****************\
     * Instructions *
     \****************/
     // clear screen
     fn cls(&mut self) {
-        self.vram = [0u64; CHIP8_DISP_H as usize];
+        for plane in self.selected_planes() {
+            self.vram[plane] = [0u128; SCHIP_DISP_H as usize];
+        }
+    }
+
+    // scroll the display down n rows
+    fn scd(&mut self, n: u8) {
+        let height = self.resolution().1 as usize;
+        let n = n as usize;
+        for plane in self.selected_planes() {
+            let vram = &mut self.vram[plane];
+            for y in (0..height).rev() {
+                vram[y] = if y >= n { vram[y - n] } else { 0 };
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    // scroll the display up n rows
+    fn scu(&mut self, n: u8) {
+        let height = self.resolution().1 as usize;
+        let n = n as usize;
+        for plane in self.selected_planes() {
+            let vram = &mut self.vram[plane];
+            for y in 0..height {
+                vram[y] = if y + n < height { vram[y + n] } else { 0 };
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    // scroll the display right 4 pixels
+    fn scr(&mut self) {
+        let mask = self.row_mask();
+        for plane in self.selected_planes() {
+            for row in self.vram[plane].iter_mut() {
+                *row = (*row << 4) & mask;
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    // scroll the display left 4 pixels
+    fn scl(&mut self) {
+        for plane in self.selected_planes() {
+            for row in self.vram[plane].iter_mut() {
+                *row >>= 4;
+            }
+        }
+        self.draw_flag = true;
+    }
+
+    // stop the interpreter
+    fn exit(&mut self) {
+        self.exited = true;
+    }
+
+    // switch between the 64x32 and 128x64 displays, which clears both planes
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        self.vram = [[0u128; SCHIP_DISP_H as usize]; 2];
+        self.draw_flag = true;
+    }
+
+    fn plane(&mut self, n: u8) {
+        self.planes = n & 0b11;
     }
 
     // return from subroutine
-    fn ret(&mut self) {
+    fn ret(&mut self) -> Result<(), Chip8Error> {
+        if self.registers.sp < 0 {
+            if self.faults.stack_underflow != FaultAction::Wrap {
+                return Err(Chip8Error::StackUnderflow {
+                    address: self.registers.pc,
+                });
+            }
+            self.registers.sp = self.stack.len() as i8 - 1;
+        }
         self.registers.pc = self.stack[self.registers.sp as usize];
         self.registers.sp -= 1;
+        Ok(())
     }
 
     // absolute jump
     fn jp(&mut self, addr: Address) {
         // set to addr - 2 because after every instruction we pc += 2
-        self.registers.pc = addr - 2;
+        self.registers.pc = addr.wrapping_sub(2);
     }
 
     // call subroutine
-    fn call(&mut self, addr: Address) {
+    fn call(&mut self, addr: Address) -> Result<(), Chip8Error> {
+        if (self.registers.sp + 1) as usize >= self.stack.len() {
+            if self.faults.stack_overflow != FaultAction::Wrap {
+                return Err(Chip8Error::StackOverflow {
+                    address: self.registers.pc,
+                });
+            }
+            self.registers.sp = -1;
+        }
         self.registers.sp += 1;
         self.stack[self.registers.sp as usize] = self.registers.pc;
-        self.registers.pc = addr - 2;
+        self.registers.pc = addr.wrapping_sub(2);
+        Ok(())
+    }
+
+    // skip the next instruction, which takes 4 bytes when it's a LD I, LONG
+    fn skip(&mut self) {
+        let next = self.fetch(self.registers.pc.wrapping_add(2));
+        self.registers.pc = self.registers.pc.wrapping_add(size_at(&next) as u16);
     }
 
     // skip next instruction if reg is equal to byte
     fn se_byte(&mut self, vx: Greg, lit: u8) {
         if self.registers.vx[vx as usize] == lit {
-            self.registers.pc += 2;
+            self.skip();
         }
     }
 
     // conditional skip
     fn sne_byte(&mut self, vx: Greg, lit: u8) {
         if self.registers.vx[vx as usize] != lit {
-            self.registers.pc += 2;
+            self.skip();
         }
     }
 
     // skip next instruction if reg is equal to another reg
     fn se_reg(&mut self, vx: Greg, vy: Greg) {
         if self.registers.vx[vx as usize] == self.registers.vx[vy as usize] {
-            self.registers.pc += 2;
+            self.skip();
         }
     }
     fn ld_byte(&mut self, vx: Greg, lit: u8) {
@@ -235,12 +868,20 @@ impl Chip8 {
     }
     fn or(&mut self, vx: Greg, vy: Greg) {
         self.registers.vx[vx as usize] |= self.registers.vx[vy as usize];
+        self.vf_reset();
     }
     fn and(&mut self, vx: Greg, vy: Greg) {
         self.registers.vx[vx as usize] &= self.registers.vx[vy as usize];
+        self.vf_reset();
     }
     fn xor(&mut self, vx: Greg, vy: Greg) {
         self.registers.vx[vx as usize] ^= self.registers.vx[vy as usize];
+        self.vf_reset();
+    }
+    fn vf_reset(&mut self) {
+        if self.quirks.vf_reset {
+            self.registers.vx[0xfusize] = 0;
+        }
     }
     fn add_reg(&mut self, vx: Greg, vy: Greg) {
         let x: usize = self.registers.vx[vx as usize] as usize;
@@ -264,66 +905,86 @@ impl Chip8 {
         let tmp = self.registers.vx[vx as usize];
         self.registers.vx[vx as usize] = tmp.wrapping_sub(self.registers.vx[vy as usize]);
     }
-    fn shr(&mut self, vx: Greg) {
-        if (self.registers.vx[vx as usize] & 0b0000_0001) == 1 {
+    fn shr(&mut self, vx: Greg, vy: Greg) {
+        let value = self.registers.vx[self.shift_source(vx, vy)];
+        if (value & 0b0000_0001) == 1 {
             self.registers.vx[0xfusize] = 1;
         } else {
             self.registers.vx[0xfusize] = 0;
         }
-        self.registers.vx[vx as usize] /= 2;
+        self.registers.vx[vx as usize] = value >> 1;
     }
     fn subn(&mut self, vx: Greg, vy: Greg) {
-        let vx: usize = self.registers.vx[vx as usize] as usize;
-        let vy: usize = self.registers.vx[vy as usize] as usize;
-        if vy > vx {
+        let x = self.registers.vx[vx as usize];
+        let y = self.registers.vx[vy as usize];
+        if y > x {
             self.registers.vx[0xfusize] = 1;
         } else {
             self.registers.vx[0xfusize] = 0;
         }
-        self.registers.vx[vx as usize] =
-            self.registers.vx[vy as usize] - self.registers.vx[vx as usize];
+        self.registers.vx[vx as usize] = y.wrapping_sub(x);
     }
-    fn shl(&mut self, vx: Greg) {
-        if (self.registers.vx[vx as usize] & 0b1000_0000) == 0b1000_0000 {
+    fn shl(&mut self, vx: Greg, vy: Greg) {
+        let value = self.registers.vx[self.shift_source(vx, vy)];
+        if (value & 0b1000_0000) == 0b1000_0000 {
             self.registers.vx[0xfusize] = 1;
         } else {
             self.registers.vx[0xfusize] = 0;
         }
-        self.registers.vx[vx as usize] *= 2;
+        self.registers.vx[vx as usize] = value << 1;
+    }
+    fn shift_source(&self, vx: Greg, vy: Greg) -> usize {
+        if self.quirks.shift_vy {
+            vy as usize
+        } else {
+            vx as usize
+        }
     }
     fn sne_reg(&mut self, vx: Greg, vy: Greg) {
         if self.registers.vx[vx as usize] != self.registers.vx[vy as usize] {
-            self.registers.pc += 2;
+            self.skip();
         }
     }
     fn ld_i(&mut self, lit: Address) {
         self.registers.i = lit;
     }
     fn jp_offset(&mut self, lit: Address) {
-        self.registers.pc = self.registers.vx[0] as u16 + lit - 2;
+        let offset = if self.quirks.jump_vx {
+            self.registers.vx[(lit >> 8) as usize & 0xf]
+        } else {
+            self.registers.vx[0]
+        };
+        self.registers.pc = (offset as u16 + lit).wrapping_sub(2);
     }
     fn rnd(&mut self, vx: Greg, lit: u8) {
         let val: u8 = rand::random();
         self.registers.vx[vx as usize] = lit & val;
     }
-    fn drw(&mut self, vx: Greg, vy: Greg, lit: u8) {
+    fn drw(&mut self, vx: Greg, vy: Greg, lit: u8) -> Result<(), Chip8Error> {
+        // with n = 0 the sprite is 16x16, two bytes to a row
+        let (width, height) = match lit & 0b0000_1111 {
+            0 => (16, 16),
+            n => (8, n as u32),
+        };
+        let row_bytes = width as usize / 8;
+        // each selected plane takes its own sprite, one after the other
+        let sprite_bytes = row_bytes * height as usize;
+        let planes: Vec<usize> = self.selected_planes().collect();
+        self.check_ram(sprite_bytes * planes.len())?;
+        let (disp_w, disp_h) = self.resolution();
         let mut erased = false;
-        for y in 0..(lit & 0b0000_1111) {
-            let spriterow = self.ram[self.registers.i as usize + y as usize];
-            for x in 0..8 {
-                let xpos = (self.registers.vx[vx as usize] as u32 + (7 - x) as u32) as u32
-                    % (CHIP8_DISP_W);
-                let ypos = (self.registers.vx[vy as usize] + y) as u32 % (CHIP8_DISP_H);
-                if DEBUG {
-                    println!(
-                        "x: {}, vx: {}, {:#04x}, xpos: {}, ypos: {}",
-                        x, vx, self.registers.vx[vx as usize], xpos, ypos
-                    );
+        // the sprite always starts on screen, but what goes past the edge may be clipped
+        let left = self.registers.vx[vx as usize] as u32 % disp_w;
+        let top = self.registers.vx[vy as usize] as u32 % disp_h;
+        for (sprite, &plane) in planes.iter().enumerate() {
+            let start = sprite * sprite_bytes;
+            for y in 0..height {
+                let mut spriterow = 0u16;
+                for byte in 0..row_bytes {
+                    let offset = start + y as usize * row_bytes + byte;
+                    spriterow = (spriterow << 8) | self.read_ram(offset) as u16;
                 }
-                let source_bit = (spriterow >> x) & 0b1;
-                let dest_bit = (self.vram[ypos as usize] >> xpos) & 0b1;
-                erased = erased || (source_bit == 1 && dest_bit == 1);
-                self.vram[ypos as usize] ^= (source_bit as u64) << xpos;
+                erased |= self.draw_row(plane, spriterow, width, left, top + y);
             }
         }
         if erased {
@@ -332,24 +993,46 @@ impl Chip8 {
             self.registers.vx[0xfusize] = 0;
         }
         self.draw_flag = true;
+        Ok(())
+    }
+    // xor a row of a sprite onto a plane, returning whether any pixels were erased
+    fn draw_row(&mut self, plane: usize, spriterow: u16, width: u32, left: u32, ypos: u32) -> bool {
+        let (disp_w, disp_h) = self.resolution();
+        let mut erased = false;
+        for x in 0..width {
+            let xpos = left + (width - 1 - x);
+            if self.quirks.clip_sprites && (xpos >= disp_w || ypos >= disp_h) {
+                continue;
+            }
+            let xpos = xpos % disp_w;
+            let ypos = ypos % disp_h;
+            let row = &mut self.vram[plane][ypos as usize];
+            let source_bit = (spriterow >> x) & 0b1;
+            let dest_bit = (*row >> xpos) & 0b1;
+            erased = erased || (source_bit == 1 && dest_bit == 1);
+            *row ^= (source_bit as u128) << xpos;
+        }
+        erased
     }
     fn skp(&mut self, vx: Greg) {
-        let reg_val = self.registers.vx[vx as usize];
-        if self.keyboard >> reg_val == 1 {
-            self.registers.pc += 2;
+        if self.key_held(vx) {
+            self.skip();
         }
     }
     fn sknp(&mut self, vx: Greg) {
-        let reg_val = self.registers.vx[vx as usize];
-        if self.keyboard >> reg_val == 0 {
-            self.registers.pc += 2;
+        if !self.key_held(vx) {
+            self.skip();
         }
     }
+    // whether the key in vx is held down, there being only 16 keys to a keypad
+    fn key_held(&self, vx: Greg) -> bool {
+        let key = self.registers.vx[vx as usize] & 0xf;
+        (self.keyboard >> key) & 1 == 1
+    }
     fn ld_vx_dt(&mut self, vx: Greg) {
         self.registers.vx[vx as usize] = self.registers.dt;
     }
     fn ld_k(&mut self, vx: Greg) {
-        //println!("Inside ld_k");
         let mut key_pressed = self.keyboard;
         if key_pressed != 0 {
             let mut key: u8 = 0;
@@ -370,287 +1053,218 @@ impl Chip8 {
         self.registers.st = self.registers.vx[vx as usize];
     }
     fn add_i(&mut self, vx: Greg) {
-        self.registers.i += self.registers.vx[vx as usize] as u16;
+        self.registers.i = self
+            .registers
+            .i
+            .wrapping_add(self.registers.vx[vx as usize] as u16);
     }
     fn ld_f(&mut self, vx: Greg) {
         self.registers.i = 5 * (self.registers.vx[vx as usize] as u16);
     }
-    fn ld_b(&mut self, vx: Greg) {
-        self.ram[(self.registers.i) as usize] = (self.registers.vx[vx as usize] / 100) % 10;
-        self.ram[(self.registers.i + 1) as usize] = (self.registers.vx[vx as usize] / 10) % 10;
-        self.ram[(self.registers.i + 2) as usize] = self.registers.vx[vx as usize] % 10;
+    fn ld_hf(&mut self, vx: Greg) {
+        let digit = self.registers.vx[vx as usize] as u16 & 0xf;
+        self.registers.i = BIG_FONT_OFFSET + 10 * digit;
+    }
+    fn ld_r_vx(&mut self, vx: Greg) {
+        let count = vx as usize + 1;
+        self.rpl[..count].copy_from_slice(&self.registers.vx[..count]);
+    }
+    fn ld_vx_r(&mut self, vx: Greg) {
+        let count = vx as usize + 1;
+        self.registers.vx[..count].copy_from_slice(&self.rpl[..count]);
+    }
+    // the registers from vx to vy, which may count down
+    fn register_range(vx: Greg, vy: Greg) -> Vec<usize> {
+        if vx <= vy {
+            (vx as usize..=vy as usize).collect()
+        } else {
+            (vy as usize..=vx as usize).rev().collect()
+        }
+    }
+    // store registers vx-vy in memory starting at address I, leaving I alone
+    fn save_range(&mut self, vx: Greg, vy: Greg) -> Result<(), Chip8Error> {
+        let registers = Chip8::register_range(vx, vy);
+        self.check_ram(registers.len())?;
+        for (offset, x) in registers.into_iter().enumerate() {
+            self.write_ram(offset, self.registers.vx[x]);
+        }
+        Ok(())
+    }
+    // read registers vx-vy from memory starting at address I, leaving I alone
+    fn load_range(&mut self, vx: Greg, vy: Greg) -> Result<(), Chip8Error> {
+        let registers = Chip8::register_range(vx, vy);
+        self.check_ram(registers.len())?;
+        for (offset, x) in registers.into_iter().enumerate() {
+            self.registers.vx[x] = self.read_ram(offset);
+        }
+        Ok(())
+    }
+    fn audio(&mut self) -> Result<(), Chip8Error> {
+        let mut pattern = [0u8; 16];
+        self.check_ram(pattern.len())?;
+        for (offset, byte) in pattern.iter_mut().enumerate() {
+            *byte = self.read_ram(offset);
+        }
+        self.audio_pattern = Some(pattern);
+        Ok(())
+    }
+    fn ld_pitch(&mut self, vx: Greg) {
+        self.pitch = self.registers.vx[vx as usize];
+    }
+    fn ld_b(&mut self, vx: Greg) -> Result<(), Chip8Error> {
+        self.check_ram(3)?;
+        let value = self.registers.vx[vx as usize];
+        self.write_ram(0, (value / 100) % 10);
+        self.write_ram(1, (value / 10) % 10);
+        self.write_ram(2, value % 10);
+        Ok(())
     }
     // store registers v0-vx in memory starting at address I
-    fn ld_s(&mut self, vx: Greg) {
+    fn ld_s(&mut self, vx: Greg) -> Result<(), Chip8Error> {
+        self.check_ram(vx as usize + 1)?;
         for x in 0..vx + 1 {
-            self.ram[(self.registers.i + x as u16) as usize] = self.registers.vx[x as usize];
+            self.write_ram(x as usize, self.registers.vx[x as usize]);
         }
+        self.load_store_increment(vx);
+        Ok(())
     }
     // read registers v0-vx from memory starting at address I
-    fn ld_r(&mut self, vx: Greg) {
+    fn ld_r(&mut self, vx: Greg) -> Result<(), Chip8Error> {
+        self.check_ram(vx as usize + 1)?;
         for x in 0..vx + 1 {
-            self.registers.vx[x as usize] = self.ram[(self.registers.i + x as u16) as usize];
+            self.registers.vx[x as usize] = self.read_ram(x as usize);
+        }
+        self.load_store_increment(vx);
+        Ok(())
+    }
+    fn load_store_increment(&mut self, vx: Greg) {
+        let increment = match self.quirks.load_store {
+            LoadStore::IncrementByXPlusOne => vx as u16 + 1,
+            LoadStore::IncrementByX => vx as u16,
+            LoadStore::Unchanged => 0,
+        };
+        self.registers.i = self.registers.i.wrapping_add(increment);
+    }
+    // check that the current instruction can touch `length` bytes of memory starting at I
+    fn check_ram(&self, length: usize) -> Result<(), Chip8Error> {
+        let end = self.registers.i as usize + length;
+        if end > self.ram.len() && self.faults.memory != FaultAction::Wrap {
+            return Err(Chip8Error::MemoryOutOfBounds {
+                address: self.registers.pc,
+                target: end - 1,
+            });
         }
+        Ok(())
+    }
+    // the address `offset` bytes past I, wrapped around the end of memory
+    fn ram_at(&self, offset: usize) -> usize {
+        (self.registers.i as usize + offset) % self.ram.len()
     }
+    // the byte `offset` bytes past I, as the current instruction reads it
+    fn read_ram(&mut self, offset: usize) -> u8 {
+        let address = self.ram_at(offset);
+        let value = self.ram[address];
+        self.watch_ram(address, Access::Read, value);
+        value
+    }
+    fn write_ram(&mut self, offset: usize, value: u8) {
+        let address = self.ram_at(offset);
+        if self.history.is_some() {
+            self.written.push((address as Address, self.ram[address]));
+        }
+        self.ram[address] = value;
+        self.watch_ram(address, Access::Write, value);
+    }
+    // stop at the first access of the instruction that a watchpoint is on
+    fn watch_ram(&mut self, address: usize, access: Access, value: u8) {
+        let address = address as Address;
+        if self.hit.is_none() && self.watchpoints.iter().any(|w| w.watches(address, access)) {
+            self.hit = Some(Hit::Memory {
+                pc: self.registers.pc,
+                address,
+                access,
+                value,
+            });
+        }
+    }
+    #[allow(dead_code)]
     fn timers_active(&self) -> bool {
         self.registers.dt > 0 || self.registers.st > 0
     }
-    fn instruction_dispatch(&mut self, upper: u8, lower: u8) {
-        let nibble1 = (upper & 0b1111_0000) >> 4;
-        let nibble2 = upper & 0b0000_1111;
-        let nibble3 = (lower & 0b1111_0000) >> 4;
-        let nibble4 = lower & 0b0000_1111;
-        if DEBUG {
-            let decomp = decompiler::decompile_word(upper, lower);
-            println!(
-                "op: {:#06x} {:<15}, pc: {:#06x}, I: {:#06x}, dt: {}, st: {}, key: {:#018b}, vx: {:?}",
-                ((upper as u16) << 8) | lower as u16,
-                decomp,
-                self.registers.pc,
-                self.registers.i,
-                self.registers.dt,
-                self.registers.st,
-                self.keyboard,
-                self.registers.vx
-            );
-        }
-        match (nibble1, nibble2, nibble3, nibble4) {
-            (0x0, 0x0, 0xe, 0x0) => self.cls(),
-            (0x0, 0x0, 0xe, 0xe) => self.ret(),
-            (0x1, n1, n2, n3) => {
-                let address = ((n1 as u16) << 8) | ((n2 as u16) << 4) | n3 as u16;
-                self.jp(address);
-            }
-            (0x2, n1, n2, n3) => {
-                let address = ((n1 as u16) << 8) | ((n2 as u16) << 4) | n3 as u16;
-                self.call(address);
-            }
-            (0x3, x, k1, k2) => {
-                let literal = ((k1 as u8) << 4) | k2 as u8;
-                self.se_byte(x, literal);
-            }
-            (0x4, x, k1, k2) => {
-                let literal = ((k1 as u8) << 4) | k2 as u8;
-                self.sne_byte(x, literal);
-            }
-            (0x5, x, y, 0x0) => {
-                self.se_reg(x, y);
-            }
-            (0x6, x, k1, k2) => {
-                let literal = ((k1 as u8) << 4) | k2 as u8;
-                self.ld_byte(x, literal);
-            }
-            (0x7, x, k1, k2) => {
-                let literal = ((k1 as u8) << 4) | k2 as u8;
-                self.add_byte(x, literal);
-            }
-            (0x8, x, y, 0x0) => {
-                self.ld_reg(x, y);
-            }
-            (0x8, x, y, 0x1) => {
-                self.or(x, y);
-            }
-            (0x8, x, y, 0x2) => {
-                self.and(x, y);
-            }
-            (0x8, x, y, 0x3) => {
-                self.xor(x, y);
-            }
-            (0x8, x, y, 0x4) => {
-                self.add_reg(x, y);
-            }
-            (0x8, x, y, 0x5) => {
-                self.sub_reg(x, y);
-            }
-            (0x8, x, _, 0x6) => {
-                self.shr(x);
-            }
-            (0x8, x, y, 0x7) => {
-                self.subn(x, y);
-            }
-            (0x8, x, _, 0xe) => {
-                self.shl(x);
-            }
-            (0x9, x, y, 0x0) => {
-                self.sne_reg(x, y);
-            }
-            (0xa, n1, n2, n3) => {
-                let address = ((n1 as u16) << 8) | ((n2 as u16) << 4) | n3 as u16;
-                self.ld_i(address);
-            }
-            (0xb, n1, n2, n3) => {
-                let address = ((n1 as u16) << 8) | ((n2 as u16) << 4) | n3 as u16;
-                self.jp_offset(address);
-            }
-            (0xc, x, k1, k2) => {
-                let literal = ((k1 as u8) << 4) | k2 as u8;
-                self.rnd(x, literal);
-            }
-            (0xd, x, y, n) => {
-                self.drw(x, y, n);
-            }
-            (0xe, x, 0x9, 0xe) => self.skp(x),
-            (0xe, x, 0xa, 0x1) => self.sknp(x),
-            (0xf, x, 0x0, 0x7) => self.ld_vx_dt(x),
-            (0xf, x, 0x0, 0xa) => self.ld_k(x),
-            (0xf, x, 0x1, 0x5) => self.ld_dt_vx(x),
-            (0xf, x, 0x1, 0x8) => self.ld_st_vx(x),
-            (0xf, x, 0x1, 0xe) => self.add_i(x),
-            (0xf, x, 0x2, 0x9) => self.ld_f(x),
-            (0xf, x, 0x3, 0x3) => self.ld_b(x),
-            (0xf, x, 0x5, 0x5) => self.ld_s(x),
-            (0xf, x, 0x6, 0x5) => self.ld_r(x),
-            (_, _, _, _) => {
-                println!("Unrecognized opcode: {:#x} {:#x}", upper, lower);
-            }
+    fn instruction_dispatch(&mut self, code: [u8; 4]) -> Result<(), Chip8Error> {
+        let instruction = decode_at(&code).map_err(|_| Chip8Error::InvalidOpcode {
+            address: self.registers.pc,
+            opcode: u16::from_be_bytes([code[0], code[1]]),
+        })?;
+        match instruction {
+            Instruction::Cls => self.cls(),
+            Instruction::Ret => self.ret()?,
+            Instruction::Jp(address) => self.jp(address),
+            Instruction::Call(address) => self.call(address)?,
+            Instruction::SeByte(x, literal) => self.se_byte(x, literal),
+            Instruction::SneByte(x, literal) => self.sne_byte(x, literal),
+            Instruction::SeReg(x, y) => self.se_reg(x, y),
+            Instruction::LdByte(x, literal) => self.ld_byte(x, literal),
+            Instruction::AddByte(x, literal) => self.add_byte(x, literal),
+            Instruction::LdReg(x, y) => self.ld_reg(x, y),
+            Instruction::Or(x, y) => self.or(x, y),
+            Instruction::And(x, y) => self.and(x, y),
+            Instruction::Xor(x, y) => self.xor(x, y),
+            Instruction::AddReg(x, y) => self.add_reg(x, y),
+            Instruction::Sub(x, y) => self.sub_reg(x, y),
+            Instruction::Shr(x, y) => self.shr(x, y),
+            Instruction::Subn(x, y) => self.subn(x, y),
+            Instruction::Shl(x, y) => self.shl(x, y),
+            Instruction::SneReg(x, y) => self.sne_reg(x, y),
+            Instruction::LdI(address) => self.ld_i(address),
+            Instruction::JpV0(address) => self.jp_offset(address),
+            Instruction::Rnd(x, literal) => self.rnd(x, literal),
+            Instruction::Drw(x, y, n) => self.drw(x, y, n)?,
+            Instruction::Skp(x) => self.skp(x),
+            Instruction::Sknp(x) => self.sknp(x),
+            Instruction::LdVxDt(x) => self.ld_vx_dt(x),
+            Instruction::LdK(x) => self.ld_k(x),
+            Instruction::LdDtVx(x) => self.ld_dt_vx(x),
+            Instruction::LdStVx(x) => self.ld_st_vx(x),
+            Instruction::AddI(x) => self.add_i(x),
+            Instruction::LdF(x) => self.ld_f(x),
+            Instruction::LdB(x) => self.ld_b(x)?,
+            Instruction::LdStore(x) => self.ld_s(x)?,
+            Instruction::LdRead(x) => self.ld_r(x)?,
+            Instruction::Scd(n) => self.scd(n),
+            Instruction::Scr => self.scr(),
+            Instruction::Scl => self.scl(),
+            Instruction::Exit => self.exit(),
+            Instruction::Low => self.set_hires(false),
+            Instruction::High => self.set_hires(true),
+            Instruction::LdHf(x) => self.ld_hf(x),
+            Instruction::LdRVx(x) => self.ld_r_vx(x),
+            Instruction::LdVxR(x) => self.ld_vx_r(x),
+            Instruction::Scu(n) => self.scu(n),
+            Instruction::LdILong(address) => self.ld_i(address),
+            Instruction::SaveRange(x, y) => self.save_range(x, y)?,
+            Instruction::LoadRange(x, y) => self.load_range(x, y)?,
+            Instruction::Plane(n) => self.plane(n),
+            Instruction::Audio => self.audio()?,
+            Instruction::LdPitch(x) => self.ld_pitch(x),
         };
-    }
-
-    pub fn handle_key(&mut self, event: Event) {
-        match event {
-            Event::KeyDown {
-                keycode: Some(Keycode::Num1),
-                ..
-            } => self.keydown(0b0000_0000_0000_0010),
-            Event::KeyDown {
-                keycode: Some(Keycode::Num2),
-                ..
-            } => self.keydown(0b0000_0000_0000_0100),
-            Event::KeyDown {
-                keycode: Some(Keycode::Num3),
-                ..
-            } => self.keydown(0b0000_0000_0000_1000),
-            Event::KeyDown {
-                keycode: Some(Keycode::Num4),
-                ..
-            } => self.keydown(0b0001_0000_0000_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::Q),
-                ..
-            } => self.keydown(0b0000_0000_0001_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::W),
-                ..
-            } => self.keydown(0b0000_0000_0010_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::E),
-                ..
-            } => self.keydown(0b0000_0000_0100_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::R),
-                ..
-            } => self.keydown(0b0010_0000_0000_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::A),
-                ..
-            } => self.keydown(0b0000_0000_1000_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::S),
-                ..
-            } => self.keydown(0b0000_0001_0000_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::D),
-                ..
-            } => self.keydown(0b0000_0010_0000_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::F),
-                ..
-            } => self.keydown(0b0100_0000_0000_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::Z),
-                ..
-            } => self.keydown(0b0000_0100_0000_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::X),
-                ..
-            } => self.keydown(0b0000_0000_0000_0001),
-            Event::KeyDown {
-                keycode: Some(Keycode::C),
-                ..
-            } => self.keydown(0b0000_1000_0000_0000),
-            Event::KeyDown {
-                keycode: Some(Keycode::V),
-                ..
-            } => self.keydown(0b1000_0000_0000_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::Num1),
-                ..
-            } => self.keyup(0b0000_0000_0000_0010),
-            Event::KeyUp {
-                keycode: Some(Keycode::Num2),
-                ..
-            } => self.keyup(0b0000_0000_0000_0100),
-            Event::KeyUp {
-                keycode: Some(Keycode::Num3),
-                ..
-            } => self.keyup(0b0000_0000_0000_1100),
-            Event::KeyUp {
-                keycode: Some(Keycode::Num4),
-                ..
-            } => self.keyup(0b0001_0000_0000_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::Q),
-                ..
-            } => self.keyup(0b0000_0000_0001_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::W),
-                ..
-            } => self.keyup(0b0000_0000_0010_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::E),
-                ..
-            } => self.keyup(0b0000_0000_0100_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::R),
-                ..
-            } => self.keyup(0b0010_0000_0000_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::A),
-                ..
-            } => self.keyup(0b0000_0000_1000_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::S),
-                ..
-            } => self.keyup(0b0000_0001_0000_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::D),
-                ..
-            } => self.keyup(0b0000_0010_0000_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::F),
-                ..
-            } => self.keyup(0b0100_0000_0000_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::Z),
-                ..
-            } => self.keyup(0b0000_0100_0000_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::X),
-                ..
-            } => self.keyup(0b0000_0000_0000_0001),
-            Event::KeyUp {
-                keycode: Some(Keycode::C),
-                ..
-            } => self.keyup(0b0000_1000_0000_0000),
-            Event::KeyUp {
-                keycode: Some(Keycode::V),
-                ..
-            } => self.keyup(0b1000_0000_0000_0000),
-            _ => (),
-        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use super::super::breakpoint::Reg;
     use super::*;
 
     #[test]
     fn test_cls() {
         let mut test_chip = Chip8::new();
-        test_chip.vram = [42u64; CHIP8_DISP_H as usize];
+        test_chip.vram = [[42u128; SCHIP_DISP_H as usize]; 2];
         test_chip.cls();
-        assert_eq!(test_chip.vram, [0u64; CHIP8_DISP_H as usize]);
+        assert_eq!(test_chip.vram[0], [0u128; SCHIP_DISP_H as usize]);
+        // only the selected plane is cleared
+        assert_eq!(test_chip.vram[1], [42u128; SCHIP_DISP_H as usize]);
     }
 
     #[test]
@@ -658,7 +1272,7 @@ mod tests {
         let mut test_chip = Chip8::new();
         test_chip.registers.sp = 0;
         test_chip.stack[test_chip.registers.sp as usize] = 0x22;
-        test_chip.ret();
+        test_chip.ret().unwrap();
         assert_eq!(test_chip.registers.sp, -1);
         assert_eq!(test_chip.registers.pc, 0x22);
     }
@@ -674,7 +1288,7 @@ mod tests {
     fn test_call() {
         let mut test_chip = Chip8::new();
         test_chip.registers.pc = 0x12;
-        test_chip.call(0x29);
+        test_chip.call(0x29).unwrap();
         assert_eq!(test_chip.registers.sp, 0);
         assert_eq!(test_chip.stack[test_chip.registers.sp as usize], 0x12);
         assert_eq!(test_chip.registers.pc, 0x27);
@@ -690,7 +1304,7 @@ mod tests {
         let mut test_chip = Chip8::new();
         test_chip.registers.vx[0x2] = 0x3;
         test_chip.se_byte(0x2, 0x4);
-        assert_eq!(test_chip.registers.pc, RAM_OFFSET + 0x0);
+        assert_eq!(test_chip.registers.pc, RAM_OFFSET);
     }
 
     #[test]
@@ -698,7 +1312,7 @@ mod tests {
         let mut test_chip = Chip8::new();
         test_chip.registers.vx[0x2] = 0x3;
         test_chip.sne_byte(0x2, 0x3);
-        assert_eq!(test_chip.registers.pc, RAM_OFFSET + 0x0);
+        assert_eq!(test_chip.registers.pc, RAM_OFFSET);
 
         let mut test_chip = Chip8::new();
         test_chip.registers.vx[0x2] = 0x3;
@@ -709,12 +1323,720 @@ mod tests {
     #[test]
     fn test_se_reg() {
         let mut test_chip = Chip8::new();
-        assert_eq!(0, 0);
+        test_chip.registers.vx[0x2] = 0x3;
+        test_chip.registers.vx[0x4] = 0x3;
+        test_chip.se_reg(0x2, 0x4);
+        assert_eq!(test_chip.registers.pc, RAM_OFFSET + 0x2);
+
+        let mut test_chip = Chip8::new();
+        test_chip.registers.vx[0x2] = 0x3;
+        test_chip.registers.vx[0x4] = 0x4;
+        test_chip.se_reg(0x2, 0x4);
+        assert_eq!(test_chip.registers.pc, RAM_OFFSET);
     }
 
     #[test]
     fn test_sne_reg() {
         let mut test_chip = Chip8::new();
-        assert_eq!(0, 0);
+        test_chip.registers.vx[0x2] = 0x3;
+        test_chip.registers.vx[0x4] = 0x3;
+        test_chip.sne_reg(0x2, 0x4);
+        assert_eq!(test_chip.registers.pc, RAM_OFFSET);
+
+        let mut test_chip = Chip8::new();
+        test_chip.registers.vx[0x2] = 0x3;
+        test_chip.registers.vx[0x4] = 0x4;
+        test_chip.sne_reg(0x2, 0x4);
+        assert_eq!(test_chip.registers.pc, RAM_OFFSET + 0x2);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut test_chip = Chip8::new();
+        test_chip.load_rom(&[0x60, 0x2a, 0x12, 0x00]).unwrap();
+        test_chip.step().unwrap();
+        assert_eq!(test_chip.registers.vx[0], 0x2a);
+        assert_eq!(test_chip.registers.pc, RAM_OFFSET + 2);
+        test_chip.step().unwrap();
+        assert_eq!(test_chip.registers.pc, RAM_OFFSET);
+    }
+
+    #[test]
+    fn test_frame() {
+        let mut test_chip = Chip8::new();
+        // ADD v0, 1 over and over
+        let rom = [0x70, 0x01].repeat(INSTRUCTIONS_PER_FRAME as usize);
+        test_chip.load_rom(&rom).unwrap();
+        test_chip.registers.dt = 2;
+        test_chip.frame().unwrap();
+        assert_eq!(test_chip.registers.vx[0], INSTRUCTIONS_PER_FRAME as u8);
+        assert_eq!(test_chip.registers.dt, 1);
+
+        test_chip.set_instructions_per_frame(3);
+        test_chip.registers.pc = RAM_OFFSET;
+        test_chip.frame().unwrap();
+        assert_eq!(test_chip.registers.vx[0], INSTRUCTIONS_PER_FRAME as u8 + 3);
+        assert_eq!(test_chip.registers.dt, 0);
+    }
+
+    #[test]
+    fn test_framebuffer() {
+        let mut test_chip = Chip8::new();
+        // LD F, v0 then DRW v0, v0, 5 draws the 0 glyph in the top left corner
+        test_chip.load_rom(&[0xf0, 0x29, 0xd0, 0x05]).unwrap();
+        test_chip.step().unwrap();
+        test_chip.step().unwrap();
+        assert!(test_chip.draw_flag());
+        assert_eq!(
+            &test_chip.framebuffer()[0][..5],
+            &[0b1111, 0b1001, 0b1001, 0b1001, 0b1111]
+        );
+        assert!(test_chip.get_vram_bit(0, 0));
+        assert!(!test_chip.get_vram_bit(1, 1));
+    }
+
+    #[test]
+    fn test_set_keys() {
+        let mut test_chip = Chip8::new();
+        // SKP v5
+        test_chip.load_rom(&[0xe5, 0x9e]).unwrap();
+        test_chip.registers.vx[5] = 5;
+        test_chip.set_keys(1 << 5);
+        test_chip.step().unwrap();
+        assert_eq!(test_chip.registers.pc, RAM_OFFSET + 4);
+    }
+
+    #[test]
+    fn test_skp_sknp() {
+        let mut test_chip = Chip8::new();
+        test_chip.set_keys(1 << 3 | 1 << 9);
+        for (key, held) in [
+            (3, true),
+            (9, true),
+            (4, false),
+            (0x13, true),
+            (0xff, false),
+        ] {
+            test_chip.registers.pc = RAM_OFFSET;
+            test_chip.registers.vx[0x1] = key;
+            test_chip.skp(0x1);
+            let skipped = test_chip.registers.pc == RAM_OFFSET + 2;
+            assert_eq!(skipped, held, "SKP with {:#x}", key);
+            test_chip.registers.pc = RAM_OFFSET;
+            test_chip.sknp(0x1);
+            let skipped = test_chip.registers.pc == RAM_OFFSET + 2;
+            assert_eq!(skipped, !held, "SKNP with {:#x}", key);
+        }
+    }
+
+    #[test]
+    fn test_sound_active() {
+        let mut test_chip = Chip8::new();
+        assert!(!test_chip.sound_active());
+        test_chip.registers.st = 1;
+        assert!(test_chip.sound_active());
+        test_chip.update_timers();
+        assert!(!test_chip.sound_active());
+    }
+
+    #[derive(Default)]
+    struct TestFrontend {
+        polls: usize,
+        presented: Vec<u128>,
+        tones: Vec<bool>,
+    }
+
+    impl Frontend for TestFrontend {
+        fn present(&mut self, planes: [&[u128]; 2]) {
+            self.presented = planes[0].to_vec();
+        }
+
+        fn poll_input(&mut self) -> Option<u16> {
+            self.polls += 1;
+            if self.polls > 4 {
+                None
+            } else {
+                Some(0)
+            }
+        }
+
+        fn start_tone(&mut self) {
+            self.tones.push(true);
+        }
+
+        fn stop_tone(&mut self) {
+            self.tones.push(false);
+        }
+    }
+
+    #[test]
+    fn test_run_with() {
+        let mut test_chip = Chip8::new();
+        // LD v0, 2; LD ST, v0; DRW v1, v1, 1; JP 0x0206
+        test_chip
+            .load_rom(&[0x60, 0x02, 0xf0, 0x18, 0xd1, 0x11, 0x12, 0x06])
+            .unwrap();
+        test_chip.registers.i = 0x202;
+        let mut frontend = TestFrontend::default();
+        test_chip.run_with(&mut frontend).unwrap();
+
+        assert_eq!(frontend.polls, 5);
+        assert_eq!(frontend.presented.len(), CHIP8_DISP_H as usize);
+        assert_eq!(frontend.presented[0], 0b1111);
+        assert_eq!(frontend.tones, vec![true, false]);
+    }
+
+    #[test]
+    fn test_load_rom_errors() {
+        let mut test_chip = Chip8::new();
+        assert!(matches!(test_chip.load_rom(&[]), Err(Chip8Error::EmptyRom)));
+        assert!(matches!(
+            test_chip.load_rom(&[0; MAX_ROM_SIZE + 1]),
+            Err(Chip8Error::RomTooLarge(size)) if size == MAX_ROM_SIZE + 1
+        ));
+        assert!(test_chip.load_rom(&[0; MAX_ROM_SIZE]).is_ok());
+        assert!(matches!(
+            Chip8::with_rom("does/not/exist.ch8".to_string()),
+            Err(Chip8Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_opcode() {
+        let mut test_chip = Chip8::new();
+        test_chip.load_rom(&[0x60, 0x01, 0xff, 0xff]).unwrap();
+        test_chip.step().unwrap();
+        assert!(matches!(
+            test_chip.step(),
+            Err(Chip8Error::InvalidOpcode {
+                address: 0x202,
+                opcode: 0xffff
+            })
+        ));
+        assert_eq!(test_chip.registers.pc, 0x202);
+    }
+
+    #[test]
+    fn test_stack_errors() {
+        let mut test_chip = Chip8::new();
+        assert!(matches!(
+            test_chip.ret(),
+            Err(Chip8Error::StackUnderflow { address: 0x200 })
+        ));
+
+        let mut test_chip = Chip8::new();
+        // CALL 0x0200, forever
+        test_chip.load_rom(&[0x22, 0x00]).unwrap();
+        for _ in 0..16 {
+            test_chip.step().unwrap();
+        }
+        assert!(matches!(
+            test_chip.step(),
+            Err(Chip8Error::StackOverflow { address: 0x200 })
+        ));
+        assert_eq!(test_chip.registers.sp, 15);
+
+        let mut registers = test_chip.registers().clone();
+        registers.sp = 16;
+        assert!(matches!(
+            test_chip.set_registers(registers.clone()),
+            Err(Chip8Error::InvalidStackPointer(16))
+        ));
+        registers.sp = -2;
+        assert!(test_chip.set_registers(registers.clone()).is_err());
+        registers.sp = -1;
+        test_chip.set_registers(registers).unwrap();
+        assert!(test_chip.stack().is_empty());
+    }
+
+    #[test]
+    fn test_memory_out_of_bounds() {
+        let mut test_chip = Chip8::new();
+        // LD I, LONG 0xfffe; LD [I], v2
+        test_chip
+            .load_rom(&[0xf0, 0x00, 0xff, 0xfe, 0xf2, 0x55])
+            .unwrap();
+        test_chip.step().unwrap();
+        assert!(matches!(
+            test_chip.step(),
+            Err(Chip8Error::MemoryOutOfBounds {
+                address: 0x204,
+                target: 0x10000
+            })
+        ));
+        test_chip.registers.vx[2] = 99;
+        assert!(test_chip.ld_r(1).is_ok());
+        test_chip.registers.i = 0xfffe;
+        assert!(test_chip.ld_b(2).is_err());
+        assert!(test_chip.drw(0, 0, 3).is_err());
+        assert_eq!(
+            test_chip.ld_b(2).unwrap_err().to_string(),
+            "instruction at 0x0204 accesses 0x10000, past the end of memory"
+        );
+    }
+
+    #[test]
+    fn test_fault_wrap() {
+        let mut test_chip = Chip8::new();
+        test_chip.set_fault_policy(FaultPolicy::all(FaultAction::Wrap));
+        // CALL 0x0200, forever
+        test_chip.load_rom(&[0x22, 0x00]).unwrap();
+        for _ in 0..17 {
+            test_chip.step().unwrap();
+        }
+        assert_eq!(test_chip.registers.sp, 0);
+
+        let mut test_chip = Chip8::new();
+        test_chip.set_fault_policy(FaultPolicy::all(FaultAction::Wrap));
+        test_chip.stack[15] = 0x300;
+        test_chip.ret().unwrap();
+        assert_eq!(test_chip.registers.pc, 0x300);
+        assert_eq!(test_chip.registers.sp, 14);
+
+        test_chip.registers.i = 0xffff;
+        test_chip.registers.vx[0] = 0xab;
+        test_chip.registers.vx[1] = 0xcd;
+        test_chip.ld_s(1).unwrap();
+        assert_eq!(test_chip.ram[0xffff], 0xab);
+        assert_eq!(test_chip.ram[0x000], 0xcd);
+    }
+
+    #[test]
+    fn test_fault_trap() {
+        let mut test_chip = Chip8::new();
+        test_chip.set_fault_policy(FaultPolicy {
+            stack_underflow: FaultAction::Trap,
+            ..FaultPolicy::default()
+        });
+        // RET with nothing to return to
+        test_chip.load_rom(&[0x00, 0xee]).unwrap();
+        test_chip.registers.dt = 5;
+        test_chip.frame().unwrap();
+        assert!(matches!(
+            test_chip.trap(),
+            Some(Chip8Error::StackUnderflow { address: 0x200 })
+        ));
+        assert_eq!(test_chip.registers.pc, 0x200);
+        assert_eq!(test_chip.registers.dt, 5);
+
+        // the instruction faults again until whatever caused it is fixed
+        test_chip.clear_trap();
+        test_chip.step().unwrap();
+        assert!(test_chip.trap().is_some());
+        test_chip.clear_trap();
+        test_chip.registers.sp = 0;
+        test_chip.stack[0] = 0x300;
+        test_chip.step().unwrap();
+        assert!(test_chip.trap().is_none());
+        assert_eq!(test_chip.registers.pc, 0x302);
+    }
+
+    #[test]
+    fn test_fault_action_from_str() {
+        assert_eq!("trap".parse(), Ok(FaultAction::Trap));
+        assert_eq!("wrap".parse(), Ok(FaultAction::Wrap));
+        assert!("explode".parse::<FaultAction>().is_err());
+    }
+
+    #[test]
+    fn test_subn() {
+        let mut test_chip = Chip8::new();
+        test_chip.registers.vx[0x1] = 0x10;
+        test_chip.registers.vx[0x2] = 0x30;
+        test_chip.subn(0x1, 0x2);
+        assert_eq!(test_chip.registers.vx[0x1], 0x20);
+        assert_eq!(test_chip.registers.vx[0xf], 1);
+        test_chip.subn(0x2, 0x1);
+        assert_eq!(test_chip.registers.vx[0x2], 0xf0);
+        assert_eq!(test_chip.registers.vx[0xf], 0);
+    }
+
+    #[test]
+    fn test_quirks_shift() {
+        let mut test_chip = Chip8::new();
+        test_chip.set_quirks(Quirks::vip());
+        test_chip.registers.vx[0x2] = 0b1000_0001;
+        test_chip.shl(0x1, 0x2);
+        assert_eq!(test_chip.registers.vx[0x1], 0b0000_0010);
+        assert_eq!(test_chip.registers.vx[0xf], 1);
+
+        test_chip.set_quirks(Quirks::schip());
+        test_chip.shr(0x2, 0x1);
+        assert_eq!(test_chip.registers.vx[0x2], 0b0100_0000);
+        assert_eq!(test_chip.registers.vx[0xf], 1);
+    }
+
+    #[test]
+    fn test_quirks_load_store() {
+        for (quirks, i) in [
+            (Quirks::vip(), 0x303),
+            (Quirks::chip48(), 0x302),
+            (Quirks::schip(), 0x300),
+        ] {
+            let mut test_chip = Chip8::new();
+            test_chip.set_quirks(quirks);
+            test_chip.registers.i = 0x300;
+            test_chip.ld_s(0x2).unwrap();
+            assert_eq!(test_chip.registers.i, i);
+        }
+    }
+
+    #[test]
+    fn test_quirks_jump() {
+        let mut test_chip = Chip8::new();
+        test_chip.registers.vx[0x0] = 0x02;
+        test_chip.registers.vx[0x3] = 0x04;
+        test_chip.jp_offset(0x300);
+        assert_eq!(test_chip.registers.pc, 0x300);
+
+        test_chip.set_quirks(Quirks::chip48());
+        test_chip.jp_offset(0x300);
+        assert_eq!(test_chip.registers.pc, 0x302);
+    }
+
+    #[test]
+    fn test_quirks_clip_sprites() {
+        // a single row 0xff sprite, drawn 4 pixels from the right hand edge
+        for (clip, wrapped) in [(true, 0), (false, 0b1111)] {
+            let mut test_chip = Chip8::new();
+            test_chip.set_quirks(Quirks {
+                clip_sprites: clip,
+                ..Quirks::vip()
+            });
+            test_chip.ram[0x300] = 0xff;
+            test_chip.registers.i = 0x300;
+            test_chip.registers.vx[0x0] = CHIP8_DISP_W as u8 + 60;
+            test_chip.drw(0x0, 0x1, 1).unwrap();
+            assert_eq!(test_chip.vram[0][0] >> 60, 0b1111);
+            assert_eq!(test_chip.vram[0][0] & 0b1111, wrapped);
+        }
+    }
+
+    #[test]
+    fn test_quirks_vf_reset() {
+        let mut test_chip = Chip8::new();
+        test_chip.set_quirks(Quirks::vip());
+        test_chip.registers.vx[0xf] = 1;
+        test_chip.or(0x1, 0x2);
+        assert_eq!(test_chip.registers.vx[0xf], 0);
+
+        test_chip.set_quirks(Quirks::schip());
+        test_chip.registers.vx[0xf] = 1;
+        test_chip.xor(0x1, 0x2);
+        assert_eq!(test_chip.registers.vx[0xf], 1);
+    }
+
+    #[test]
+    fn test_hires() {
+        let mut test_chip = Chip8::new();
+        test_chip.vram[0][0] = 0b1;
+        // HIGH, then a single row sprite at x 120, which is only on screen in hi-res mode
+        test_chip.load_rom(&[0x00, 0xff, 0xd0, 0x11]).unwrap();
+        test_chip.ram[0x300] = 0xff;
+        test_chip.registers.i = 0x300;
+        test_chip.registers.vx[0x0] = 120;
+        test_chip.step().unwrap();
+        assert!(test_chip.hires());
+        assert_eq!(test_chip.framebuffer()[0].len(), SCHIP_DISP_H as usize);
+        assert_eq!(test_chip.vram[0][0], 0);
+        test_chip.step().unwrap();
+        assert_eq!(test_chip.vram[0][0], 0xff << 120);
+
+        test_chip.set_hires(false);
+        assert_eq!(test_chip.framebuffer()[0].len(), CHIP8_DISP_H as usize);
+        assert_eq!(test_chip.vram[0][0], 0);
+    }
+
+    #[test]
+    fn test_drw_16x16() {
+        let mut test_chip = Chip8::new();
+        test_chip.ram[0x300] = 0b1000_0000;
+        test_chip.ram[0x301] = 0b0000_0001;
+        // the left hand half of the last row
+        test_chip.ram[0x31e] = 0xff;
+        test_chip.registers.i = 0x300;
+        test_chip.drw(0x0, 0x1, 0).unwrap();
+        assert_eq!(test_chip.vram[0][0], 0b1000_0000_0000_0001);
+        assert_eq!(test_chip.vram[0][1..15], [0; 14]);
+        assert_eq!(test_chip.vram[0][15], 0x00ff);
+        assert_eq!(test_chip.registers.vx[0xf], 0);
+
+        test_chip.registers.i = 0xffe1;
+        assert!(test_chip.drw(0x0, 0x1, 0).is_err());
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut test_chip = Chip8::new();
+        test_chip.vram[0][0] = 0b1;
+        test_chip.vram[0][1] = 0xf << 60;
+        test_chip.scd(2);
+        assert_eq!(test_chip.vram[0][..4], [0, 0, 0b1, 0xf << 60]);
+        test_chip.scr();
+        // pixels scrolled past the right hand edge are lost
+        assert_eq!(test_chip.vram[0][..4], [0, 0, 0b1_0000, 0]);
+        test_chip.scl();
+        test_chip.scl();
+        assert_eq!(test_chip.vram[0][2], 0);
+
+        test_chip.set_hires(true);
+        test_chip.vram[0][0] = 0xf << 60;
+        test_chip.scr();
+        assert_eq!(test_chip.vram[0][0], 0xf << 64);
+        test_chip.scd(63);
+        assert_eq!(test_chip.vram[0][63], 0xf << 64);
+    }
+
+    #[test]
+    fn test_exit() {
+        let mut test_chip = Chip8::new();
+        // EXIT, then an invalid opcode that must never run
+        test_chip.load_rom(&[0x00, 0xfd, 0x01, 0x23]).unwrap();
+        test_chip.registers.dt = 5;
+        test_chip.frame().unwrap();
+        assert!(test_chip.exited());
+        assert_eq!(test_chip.registers.dt, 5);
+
+        // the run loop stops at the first poll rather than waiting to be asked to quit
+        let mut frontend = TestFrontend {
+            polls: 0,
+            presented: Vec::new(),
+            tones: Vec::new(),
+        };
+        test_chip.run_with(&mut frontend).unwrap();
+        assert_eq!(frontend.polls, 1);
+    }
+
+    #[test]
+    fn test_ld_hf() {
+        let mut test_chip = Chip8::new();
+        test_chip.registers.vx[0x2] = 0x3;
+        test_chip.ld_hf(0x2);
+        assert_eq!(test_chip.registers.i, BIG_FONT_OFFSET + 30);
+        assert_eq!(test_chip.ram[test_chip.registers.i as usize], 0xff);
+        assert_eq!(test_chip.ram[test_chip.registers.i as usize + 9], 0xff);
+    }
+
+    #[test]
+    fn test_rpl() {
+        let mut test_chip = Chip8::new();
+        for x in 0..16 {
+            test_chip.registers.vx[x] = x as u8 + 1;
+        }
+        test_chip.ld_r_vx(0xf);
+        assert_eq!(test_chip.rpl[..8], [1, 2, 3, 4, 5, 6, 7, 8]);
+        assert_eq!(test_chip.rpl[15], 16);
+        test_chip.registers.vx = [0; 16];
+        test_chip.ld_vx_r(0x2);
+        assert_eq!(test_chip.registers.vx[..4], [1, 2, 3, 0]);
+    }
+
+    #[test]
+    fn test_ld_i_long() {
+        let mut test_chip = Chip8::new();
+        // SE v0, 0x00 skips the whole of the LD I, LONG after it
+        test_chip
+            .load_rom(&[0x30, 0x00, 0xf0, 0x00, 0x12, 0x34, 0xf0, 0x00, 0xab, 0xcd])
+            .unwrap();
+        test_chip.step().unwrap();
+        assert_eq!(test_chip.registers.pc, 0x206);
+        test_chip.step().unwrap();
+        assert_eq!(test_chip.registers.i, 0xabcd);
+        assert_eq!(test_chip.registers.pc, 0x20a);
+    }
+
+    #[test]
+    fn test_register_ranges() {
+        let mut test_chip = Chip8::new();
+        test_chip.registers.i = 0x300;
+        test_chip.registers.vx[0x2] = 2;
+        test_chip.registers.vx[0x3] = 3;
+        test_chip.registers.vx[0x4] = 4;
+        test_chip.save_range(0x2, 0x4).unwrap();
+        assert_eq!(test_chip.ram[0x300..0x303], [2, 3, 4]);
+        assert_eq!(test_chip.registers.i, 0x300);
+        test_chip.load_range(0x7, 0x5).unwrap();
+        assert_eq!(test_chip.registers.vx[0x5..0x8], [4, 3, 2]);
+        assert_eq!(test_chip.registers.i, 0x300);
+    }
+
+    #[test]
+    fn test_planes() {
+        let mut test_chip = Chip8::new();
+        test_chip.ram[0x300] = 0b1100_0000;
+        test_chip.ram[0x301] = 0b1010_0000;
+        test_chip.registers.i = 0x300;
+        // both planes take a sprite each, from consecutive bytes
+        test_chip.plane(0b11);
+        test_chip.drw(0x0, 0x1, 1).unwrap();
+        assert_eq!(test_chip.framebuffer()[0][0], 0b11);
+        assert_eq!(test_chip.framebuffer()[1][0], 0b101);
+
+        test_chip.plane(0b10);
+        test_chip.drw(0x0, 0x1, 1).unwrap();
+        assert_eq!(test_chip.registers.vx[0xf], 1);
+        assert_eq!(test_chip.framebuffer()[1][0], 0b110);
+        test_chip.cls();
+        assert_eq!(test_chip.framebuffer()[0][0], 0b11);
+        assert_eq!(test_chip.framebuffer()[1][0], 0);
+
+        test_chip.plane(0);
+        test_chip.drw(0x0, 0x1, 1).unwrap();
+        assert_eq!(test_chip.framebuffer()[0][0], 0b11);
+    }
+
+    #[test]
+    fn test_scu() {
+        let mut test_chip = Chip8::new();
+        test_chip.vram[0][3] = 0b1;
+        test_chip.vram[1][3] = 0b1;
+        test_chip.scu(2);
+        assert_eq!(test_chip.vram[0][..4], [0, 0b1, 0, 0]);
+        assert_eq!(test_chip.vram[1][3], 0b1);
+    }
+
+    #[test]
+    fn test_audio() {
+        let mut test_chip = Chip8::new();
+        for offset in 0..16 {
+            test_chip.ram[0x300 + offset] = offset as u8;
+        }
+        test_chip.registers.i = 0x300;
+        assert_eq!(test_chip.audio_pattern(), None);
+        test_chip.audio().unwrap();
+        assert_eq!(test_chip.audio_pattern().unwrap()[15], 15);
+        assert_eq!(test_chip.pitch(), 64);
+        test_chip.registers.vx[0x1] = 112;
+        test_chip.ld_pitch(0x1);
+        assert_eq!(test_chip.pitch(), 112);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut test_chip = Chip8::new();
+        // LD v0, 123; LD I, 0x0300; LD B, v0; LD v1, [I]; JP 0x0208
+        test_chip
+            .load_rom(&[0x60, 0x7b, 0xa3, 0x00, 0xf0, 0x33, 0xf1, 0x65, 0x12, 0x08])
+            .unwrap();
+        test_chip.add_watchpoint(Watchpoint::Register(Reg::I));
+        test_chip.add_watchpoint(Watchpoint::Memory {
+            start: 0x0301,
+            end: 0x0302,
+            reads: false,
+            writes: true,
+        });
+        test_chip.add_watchpoint(Watchpoint::Memory {
+            start: 0x0300,
+            end: 0x0300,
+            reads: true,
+            writes: false,
+        });
+        assert!(test_chip.frame_until(|_| false).unwrap());
+        assert_eq!(
+            test_chip.hit(),
+            Some(&Hit::Register {
+                pc: 0x0202,
+                reg: Reg::I,
+                old: 0,
+                new: 0x0300
+            })
+        );
+        test_chip.frame_until(|_| false).unwrap();
+        assert_eq!(
+            test_chip.hit(),
+            Some(&Hit::Memory {
+                pc: 0x0204,
+                address: 0x0301,
+                access: Access::Write,
+                value: 2
+            })
+        );
+        assert_eq!(test_chip.registers.pc, 0x0206);
+        // LD vx, [I] reads 0x0300 first, and then moves I on
+        test_chip.frame_until(|_| false).unwrap();
+        assert_eq!(
+            test_chip.hit(),
+            Some(&Hit::Memory {
+                pc: 0x0206,
+                address: 0x0300,
+                access: Access::Read,
+                value: 1
+            })
+        );
+        assert_eq!(
+            test_chip.remove_watchpoint(0),
+            Some(Watchpoint::Register(Reg::I))
+        );
+        assert!(!test_chip.frame_until(|_| false).unwrap());
+        assert_eq!(test_chip.hit(), None);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut test_chip = Chip8::new();
+        // ADD v0, 0x01; JP 0x0200
+        test_chip.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        test_chip.add_breakpoint(Breakpoint::when(0x0202, "v0 == 3".parse().unwrap()));
+        assert!(test_chip.frame_until(|_| false).unwrap());
+        assert_eq!(test_chip.hit(), Some(&Hit::Breakpoint(0x0202)));
+        assert_eq!(test_chip.registers.vx[0], 3);
+        // an unconditional breakpoint replaces it
+        test_chip.add_breakpoint(Breakpoint::new(0x0202));
+        assert_eq!(test_chip.breakpoints(), [Breakpoint::new(0x0202)]);
+        test_chip.step().unwrap();
+        test_chip.step().unwrap();
+        assert_eq!(test_chip.hit(), Some(&Hit::Breakpoint(0x0202)));
+        assert_eq!(test_chip.registers.vx[0], 4);
+        assert!(test_chip.remove_breakpoint(0x0202));
+        assert!(!test_chip.remove_breakpoint(0x0202));
+    }
+
+    #[test]
+    fn test_rewind() {
+        let mut test_chip = Chip8::new();
+        test_chip.set_rewind(100, 1 << 20);
+        test_chip.set_instructions_per_frame(6);
+        // LD I, 0x300; LD v0, 0x7b; LD B, v0; DRW v1, v1, 1; LD DT, v0; JP 0x020a
+        let rom = [
+            0xa3, 0x00, 0x60, 0x7b, 0xf0, 0x33, 0xd1, 0x11, 0xf0, 0x15, 0x12, 0x0a,
+        ];
+        test_chip.load_rom(&rom).unwrap();
+        test_chip.frame().unwrap();
+        assert_eq!(test_chip.ram[0x300..0x303], [1, 2, 3]);
+        assert_ne!(test_chip.vram[0][0], 0);
+        assert_eq!(test_chip.registers.dt, 0x7a);
+        assert_eq!(test_chip.history().unwrap().instructions(), 6);
+
+        assert!(test_chip.rewind_frame());
+        assert_eq!(
+            test_chip.registers,
+            Chip8Regs {
+                sp: -1,
+                pc: 0x200,
+                ..Chip8Regs::default()
+            }
+        );
+        assert_eq!(test_chip.ram[0x300..0x303], [0, 0, 0]);
+        assert_eq!(test_chip.vram[0][0], 0);
+        assert!(!test_chip.rewind_frame());
+        assert!(!test_chip.step_back());
+
+        test_chip.frame().unwrap();
+        // the tick of the timers goes along with the JP before it
+        assert!(test_chip.step_back());
+        assert_eq!(test_chip.registers.dt, 0x7b);
+        assert!(test_chip.step_back());
+        assert_eq!((test_chip.registers.pc, test_chip.registers.dt), (0x208, 0));
+        assert!(test_chip.step_back());
+        assert_eq!(test_chip.vram[0][0], 0);
+        assert!(test_chip.step_back());
+        assert_eq!(test_chip.ram[0x300..0x303], [0, 0, 0]);
+
+        test_chip.add_breakpoint(Breakpoint::new(0x0202));
+        test_chip.frame().unwrap();
+        assert!(test_chip.reverse_continue());
+        assert_eq!(test_chip.hit(), Some(&Hit::Breakpoint(0x0202)));
+        assert_eq!(test_chip.registers.i, 0x300);
+        assert_eq!(test_chip.history().unwrap().instructions(), 1);
     }
 }
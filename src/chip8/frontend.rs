@@ -0,0 +1,57 @@
+// what the emulator needs from whatever is showing the display, playing sound and reading the
+// keypad, so that the run loop doesn't care whether that's SDL, a terminal or a test
+pub trait Frontend {
+    // show the display's two planes, each one u128 per row with the pixel in column x at bit x. It
+    // is always twice as wide as it is tall: 64x32, or 128x64 in hi-res mode. Each pixel is one of
+    // four colours, with bit p of the colour set when the pixel is set in plane p
+    fn present(&mut self, planes: [&[u128]; 2]);
+
+    // the state of the whole keypad, with key n held down when bit n is set, or None once the
+    // user has asked to quit
+    fn poll_input(&mut self) -> Option<u16>;
+
+    // whether the user is holding down whatever rewinds the game, as of the last poll_input
+    fn rewinding(&self) -> bool {
+        false
+    }
+
+    fn start_tone(&mut self);
+
+    fn stop_tone(&mut self);
+
+    // XO-CHIP's 1-bit audio pattern and its pitch, to play instead of the usual tone from then on,
+    // or None to go back to the tone. Front-ends that can't play patterns keep to the tone
+    fn set_pattern(&mut self, _pattern: Option<&[u8; 16]>, _pitch: u8) {}
+}
+
+// what the debuggers' tests run against
+#[cfg(test)]
+pub(crate) mod testing {
+    use super::Frontend;
+
+    // quits after a handful of polls, so that continuing can't run forever
+    #[derive(Default)]
+    pub(crate) struct QuitFrontend {
+        polls: usize,
+    }
+
+    impl Frontend for QuitFrontend {
+        fn present(&mut self, _planes: [&[u128]; 2]) {}
+
+        fn poll_input(&mut self) -> Option<u16> {
+            self.polls += 1;
+            if self.polls > 5 {
+                None
+            } else {
+                Some(0)
+            }
+        }
+
+        fn start_tone(&mut self) {}
+
+        fn stop_tone(&mut self) {}
+    }
+
+    // LD v0, 0x05; ADD v0, 0x01; CALL 0x0208; JP 0x0202; RET
+    pub(crate) const ROM: [u8; 10] = [0x60, 0x05, 0x70, 0x01, 0x22, 0x08, 0x12, 0x02, 0x00, 0xee];
+}
@@ -0,0 +1,565 @@
+use super::breakpoint::{Breakpoint, Reg};
+use super::constants::FRAME_RATE;
+use super::emulator::{Chip8, Chip8Error, Chip8Regs};
+use super::frontend::Frontend;
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+// GDB's register numbers: v0-vF, then I, pc, sp, dt and st
+const REGISTERS: [Reg; 21] = [
+    Reg::V(0x0),
+    Reg::V(0x1),
+    Reg::V(0x2),
+    Reg::V(0x3),
+    Reg::V(0x4),
+    Reg::V(0x5),
+    Reg::V(0x6),
+    Reg::V(0x7),
+    Reg::V(0x8),
+    Reg::V(0x9),
+    Reg::V(0xa),
+    Reg::V(0xb),
+    Reg::V(0xc),
+    Reg::V(0xd),
+    Reg::V(0xe),
+    Reg::V(0xf),
+    Reg::I,
+    Reg::Pc,
+    Reg::Sp,
+    Reg::Dt,
+    Reg::St,
+];
+
+// the signals a stop is reported as
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+const SIGKILL: u8 = 9;
+const SIGSEGV: u8 = 11;
+
+// a link to a GDB client, which has to be able to interrupt a running ROM
+pub trait Connection: Read + Write {
+    // whether the client has sent an interrupt, a 0x03 byte, since the ROM was last resumed.
+    // This mustn't block
+    fn interrupted(&mut self) -> bool;
+}
+
+impl Connection for TcpStream {
+    fn interrupted(&mut self) -> bool {
+        let mut byte = [0u8];
+        if self.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let interrupted = matches!(self.peek(&mut byte), Ok(1) if byte[0] == 0x03);
+        if interrupted {
+            let _ = self.read(&mut byte);
+        }
+        let _ = self.set_nonblocking(false);
+        interrupted
+    }
+}
+
+// the bytes of a register, in GDB's little-endian order
+fn width(reg: Reg) -> usize {
+    match reg {
+        Reg::I | Reg::Pc => 2,
+        _ => 1,
+    }
+}
+
+// a stub for GDB's remote serial protocol, which lets a GDB client debug a ROM
+pub struct GdbStub<C: Connection> {
+    connection: C,
+    // whether packets are acknowledged, which the client can turn off
+    ack: bool,
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub fn new(connection: C) -> GdbStub<C> {
+        GdbStub {
+            connection,
+            ack: true,
+        }
+    }
+
+    // answer the client's packets until it detaches, kills the ROM or hangs up. The machine is
+    // stopped whenever the client isn't continuing it
+    pub fn serve<F: Frontend>(&mut self, chip8: &mut Chip8, frontend: &mut F) -> io::Result<()> {
+        frontend.present(chip8.framebuffer());
+        while let Some(packet) = self.receive()? {
+            match packet.as_str() {
+                "D" => return self.send("OK"),
+                // killing the ROM gets no reply
+                "k" => return Ok(()),
+                _ => {
+                    let reply = self.reply(chip8, frontend, &packet);
+                    self.send(&reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the next packet's data, or None once the client hangs up. Anything outside a packet, such as
+    // acknowledgements, is skipped, as is a packet with the wrong checksum. That's sent again after
+    // a `-`, or answered with an error once acknowledgements are off
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => (),
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0u8; 2];
+            self.connection.read_exact(&mut checksum)?;
+            let valid = u8::from_str_radix(&String::from_utf8_lossy(&checksum), 16)
+                == Ok(checksum_of(&data));
+            if self.ack {
+                self.connection.write_all(if valid { b"+" } else { b"-" })?;
+            }
+            if valid {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            if !self.ack {
+                self.send("E01")?;
+            }
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0u8];
+        match self.connection.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.connection.write_all(packet.as_bytes())?;
+        self.connection.flush()
+    }
+
+    // the reply to a packet, which is empty for anything that isn't supported
+    fn reply<F: Frontend>(&mut self, chip8: &mut Chip8, frontend: &mut F, packet: &str) -> String {
+        // the first character, which may be more than a byte if the client sent something invalid
+        let first = packet.chars().next().map_or(0, char::len_utf8);
+        let (command, args) = packet.split_at(first);
+        let reply = match command {
+            "?" => Some(stop_reply(chip8)),
+            "g" => Some(hex(&read_registers(chip8, &REGISTERS))),
+            "G" => unhex(args).and_then(|bytes| write_registers(chip8, &REGISTERS, &bytes)),
+            "p" => register(args).map(|reg| hex(&read_registers(chip8, &[reg]))),
+            "P" => args.split_once('=').and_then(|(number, value)| {
+                write_registers(chip8, &[register(number)?], &unhex(value)?)
+            }),
+            "m" => memory_range(chip8, args).map(|(start, end)| hex(&chip8.memory()[start..end])),
+            "M" => args.split_once(':').and_then(|(range, data)| {
+                let (start, end) = memory_range(chip8, range)?;
+                let bytes = unhex(data).filter(|bytes| bytes.len() == end - start)?;
+                chip8.memory_mut()[start..end].copy_from_slice(&bytes);
+                Some("OK".to_string())
+            }),
+            "s" => Some(self.step(chip8, frontend)),
+            "c" => Some(self.resume(chip8, frontend)),
+            "b" if chip8.history().is_some() => match args {
+                "s" => Some(reverse(chip8, frontend, Chip8::step_back)),
+                "c" => Some(reverse(chip8, frontend, Chip8::reverse_continue)),
+                _ => None,
+            },
+            "Z" | "z" => breakpoint(args).map(|address| {
+                if command == "Z" {
+                    chip8.add_breakpoint(Breakpoint::new(address));
+                } else {
+                    chip8.remove_breakpoint(address);
+                }
+                "OK".to_string()
+            }),
+            "H" => Some("OK".to_string()),
+            _ => self.query(chip8, packet),
+        };
+        reply.unwrap_or_else(|| match command {
+            // bad arguments to a packet that is supported
+            "G" | "p" | "P" | "m" | "M" => "E01".to_string(),
+            _ => String::new(),
+        })
+    }
+
+    // the general queries and settings that are supported
+    fn query(&mut self, chip8: &Chip8, packet: &str) -> Option<String> {
+        if packet.starts_with("qSupported") {
+            let mut features = "PacketSize=4000;qXfer:features:read+;QStartNoAckMode+".to_string();
+            if chip8.history().is_some() {
+                features.push_str(";ReverseStep+;ReverseContinue+");
+            }
+            return Some(features);
+        }
+        if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+            let (offset, length) = range.split_once(',')?;
+            let offset = usize::from_str_radix(offset, 16).ok()?;
+            let length = usize::from_str_radix(length, 16).ok()?;
+            let xml = target_xml();
+            let start = offset.min(xml.len());
+            let end = (start + length).min(xml.len());
+            let more = if end < xml.len() { "m" } else { "l" };
+            return Some(format!("{}{}", more, &xml[start..end]));
+        }
+        match packet {
+            "QStartNoAckMode" => {
+                self.ack = false;
+                Some("OK".to_string())
+            }
+            "qAttached" => Some("1".to_string()),
+            _ => None,
+        }
+    }
+
+    fn step<F: Frontend>(&mut self, chip8: &mut Chip8, frontend: &mut F) -> String {
+        let stepped = chip8.step();
+        if chip8.draw_flag() {
+            frontend.present(chip8.framebuffer());
+        }
+        match stepped {
+            Err(e) => format!("S{:02x}", signal(&e)),
+            Ok(()) => stop_reply(chip8),
+        }
+    }
+
+    // run in real time until a breakpoint, a fault or the client interrupts
+    fn resume<F: Frontend>(&mut self, chip8: &mut Chip8, frontend: &mut F) -> String {
+        let connection = &mut self.connection;
+        let mut interrupted = false;
+        let frame = Duration::from_secs(1) / FRAME_RATE;
+        let mut polled = Instant::now();
+        let stopped = chip8.run_until(frontend, |chip8| {
+            // looking for an interrupt takes a few syscalls, so it's only done once a frame
+            if !interrupted && polled.elapsed() >= frame {
+                polled = Instant::now();
+                interrupted = connection.interrupted();
+            }
+            chip8.trap().is_some() || interrupted
+        });
+        match stopped {
+            Err(e) => format!("S{:02x}", signal(&e)),
+            Ok(_) if interrupted => format!("S{:02x}", SIGINT),
+            Ok(true) => stop_reply(chip8),
+            Ok(false) if chip8.exited() => "W00".to_string(),
+            // the front-end was asked to quit
+            Ok(false) => format!("X{:02x}", SIGKILL),
+        }
+    }
+}
+
+// step back with `back`, which returns false if it ran out of history, in which case GDB is told
+// that it has come to the start of the recording
+fn reverse<F: Frontend>(
+    chip8: &mut Chip8,
+    frontend: &mut F,
+    back: fn(&mut Chip8) -> bool,
+) -> String {
+    let stopped = back(chip8);
+    frontend.present(chip8.framebuffer());
+    if stopped {
+        stop_reply(chip8)
+    } else {
+        format!("T{:02x}replaylog:begin;", SIGTRAP)
+    }
+}
+
+// the reply for a machine that has stopped at a breakpoint, a trap or the end of the ROM
+fn stop_reply(chip8: &Chip8) -> String {
+    match chip8.trap() {
+        _ if chip8.exited() => "W00".to_string(),
+        Some(trap) => format!("S{:02x}", signal(trap)),
+        None => format!("S{:02x}", SIGTRAP),
+    }
+}
+
+fn signal(error: &Chip8Error) -> u8 {
+    match error {
+        Chip8Error::InvalidOpcode { .. } => SIGILL,
+        Chip8Error::StackOverflow { .. }
+        | Chip8Error::StackUnderflow { .. }
+        | Chip8Error::MemoryOutOfBounds { .. } => SIGSEGV,
+        _ => SIGTRAP,
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    text.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair {
+            [_, _] => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+fn register(number: &str) -> Option<Reg> {
+    let number = usize::from_str_radix(number, 16).ok()?;
+    REGISTERS.get(number).copied()
+}
+
+fn read_registers(chip8: &Chip8, registers: &[Reg]) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    for &reg in registers {
+        let value = reg.value(chip8.registers()) as u16;
+        bytes.extend_from_slice(&value.to_le_bytes()[..width(reg)]);
+    }
+    bytes
+}
+
+// set the registers from their bytes, all or none of them
+fn write_registers(chip8: &mut Chip8, registers: &[Reg], bytes: &[u8]) -> Option<String> {
+    if bytes.len() != registers.iter().map(|&reg| width(reg)).sum::<usize>() {
+        return None;
+    }
+    let mut values: Chip8Regs = chip8.registers().clone();
+    let mut offset = 0;
+    for &reg in registers {
+        let value = match width(reg) {
+            2 => u16::from_le_bytes([bytes[offset], bytes[offset + 1]]),
+            _ => bytes[offset] as u16,
+        };
+        offset += width(reg);
+        match reg {
+            Reg::V(x) => values.vx[x as usize] = value as u8,
+            Reg::I => values.i = value,
+            Reg::Pc => values.pc = value,
+            Reg::Sp => values.sp = value as u8 as i8,
+            Reg::Dt => values.dt = value as u8,
+            Reg::St => values.st = value as u8,
+        }
+    }
+    chip8.set_registers(values).ok()?;
+    Some("OK".to_string())
+}
+
+// the start and end of `address,length`, which has to be in memory
+fn memory_range(chip8: &Chip8, range: &str) -> Option<(usize, usize)> {
+    let (address, length) = range.split_once(',')?;
+    let start = usize::from_str_radix(address, 16).ok()?;
+    let end = start.checked_add(usize::from_str_radix(length, 16).ok()?)?;
+    if end > chip8.memory().len() {
+        return None;
+    }
+    Some((start, end))
+}
+
+// the address of a `0,addr,kind` software breakpoint. Hardware breakpoints and watchpoints
+// aren't supported
+fn breakpoint(args: &str) -> Option<u16> {
+    let mut fields = args.split(',');
+    if fields.next()? != "0" {
+        return None;
+    }
+    u16::from_str_radix(fields.next()?, 16).ok()
+}
+
+// the description of the registers that GDB asks for
+fn target_xml() -> String {
+    let mut registers: Vec<String> = (0..16)
+        .map(|x| format!("<reg name=\"v{:x}\" bitsize=\"8\" type=\"uint8\"/>", x))
+        .collect();
+    registers.push("<reg name=\"i\" bitsize=\"16\" type=\"data_ptr\"/>".to_string());
+    registers.push("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>".to_string());
+    registers.push("<reg name=\"sp\" bitsize=\"8\" type=\"int8\"/>".to_string());
+    registers.push("<reg name=\"dt\" bitsize=\"8\" type=\"uint8\"/>".to_string());
+    registers.push("<reg name=\"st\" bitsize=\"8\" type=\"uint8\"/>".to_string());
+    format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\"><feature name=\"org.chip8.core\">{}</feature></target>",
+        registers.concat()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::frontend::testing::{QuitFrontend, ROM};
+    use super::*;
+    use std::io::Cursor;
+
+    struct Pipe {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Pipe {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Pipe {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for Pipe {
+        fn interrupted(&mut self) -> bool {
+            false
+        }
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+    }
+
+    // the replies to the packets, and the machine afterwards
+    fn session(packets: &[&str]) -> (Vec<String>, Chip8) {
+        let mut chip8 = Chip8::new();
+        chip8.set_rewind(100, 1 << 20);
+        chip8.load_rom(&ROM).unwrap();
+        let input: String = packets.iter().map(|data| packet(data)).collect();
+        let mut stub = GdbStub::new(Pipe {
+            input: Cursor::new(input.into_bytes()),
+            output: Vec::new(),
+        });
+        stub.serve(&mut chip8, &mut QuitFrontend::default())
+            .unwrap();
+        let output = String::from_utf8(stub.connection.output).unwrap();
+        let replies = output
+            .split('$')
+            .skip(1)
+            .map(|reply| reply[..reply.find('#').unwrap()].to_string())
+            .collect();
+        (replies, chip8)
+    }
+
+    #[test]
+    fn test_packets() {
+        let mut stub = GdbStub::new(Pipe {
+            input: Cursor::new(b"+$?#3f$?#00$QStartNoAckMode#b0$?#3f".to_vec()),
+            output: Vec::new(),
+        });
+        stub.serve(&mut Chip8::new(), &mut QuitFrontend::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(stub.connection.output).unwrap(),
+            "+$S05#b8-+$OK#9a$S05#b8"
+        );
+    }
+
+    #[test]
+    fn test_invalid_packets() {
+        let mut stub = GdbStub::new(Pipe {
+            input: Cursor::new(b"$QStartNoAckMode#b0$\xffg#66$#00$?#00$?#3f".to_vec()),
+            output: Vec::new(),
+        });
+        stub.serve(&mut Chip8::new(), &mut QuitFrontend::default())
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(stub.connection.output).unwrap(),
+            "+$OK#9a$#00$#00$E01#a6$S05#b8"
+        );
+    }
+
+    #[test]
+    fn test_registers() {
+        let (replies, chip8) = session(&[
+            "g",
+            "p11",
+            "P3=7f",
+            "P10=0003",
+            "P12=10",
+            "p3",
+            "G0001027f0405060708090a0b0c0d0e0f00030002ff0000",
+            "G00",
+        ]);
+        assert_eq!(
+            replies[..3],
+            [
+                "0000000000000000000000000000000000000002ff0000",
+                "0002",
+                "OK",
+            ]
+        );
+        assert_eq!(replies[3..], ["OK", "E01", "7f", "OK", "E01"]);
+        assert_eq!(chip8.registers().vx[0x3], 0x7f);
+        assert_eq!(chip8.registers().i, 0x0300);
+    }
+
+    #[test]
+    fn test_memory() {
+        let (replies, chip8) =
+            session(&["m200,4", "M300,2:abcd", "m300,2", "mffff,2", "M300,2:ab"]);
+        assert_eq!(replies, ["60057001", "OK", "abcd", "E01", "E01"]);
+        assert_eq!(chip8.memory()[0x301], 0xcd);
+    }
+
+    #[test]
+    fn test_step_and_breakpoints() {
+        let (replies, chip8) = session(&[
+            "s", "Z0,208,2", "c", "p11", "z0,208,2", "Z1,208,2", "D", "s",
+        ]);
+        assert_eq!(replies, ["S05", "OK", "S05", "0802", "OK", "", "OK"]);
+        assert_eq!(chip8.registers().pc, 0x0208);
+        assert!(chip8.breakpoints().is_empty());
+        // with no breakpoints, the front-end quits
+        let (replies, _) = session(&["c"]);
+        assert_eq!(replies, ["X09"]);
+        // a breakpoint on the instruction about to run stops before it
+        let (replies, _) = session(&["Z0,200,2", "c", "p11", "c"]);
+        assert_eq!(replies, ["OK", "S05", "0002", "X09"]);
+        // LD v0, 0x05 is followed by an invalid opcode
+        let (replies, _) = session(&["M202,2:0000", "s", "s"]);
+        assert_eq!(replies, ["OK", "S05", "S04"]);
+    }
+
+    #[test]
+    fn test_reverse() {
+        let (replies, chip8) = session(&["s", "s", "s", "bs", "p11", "Z0,202,2", "bc", "bc"]);
+        assert_eq!(
+            replies,
+            [
+                "S05",
+                "S05",
+                "S05",
+                "S05",
+                "0402",
+                "OK",
+                "S05",
+                "T05replaylog:begin;"
+            ]
+        );
+        assert_eq!(chip8.registers().pc, 0x0200);
+        assert_eq!(chip8.registers().vx[0], 0);
+    }
+
+    #[test]
+    fn test_target_xml() {
+        let xml = target_xml();
+        let (replies, _) = session(&[
+            "qSupported:multiprocess+",
+            "qXfer:features:read:target.xml:0,10",
+            &format!("qXfer:features:read:target.xml:10,{:x}", xml.len()),
+        ]);
+        assert!(replies[0].contains("qXfer:features:read+"));
+        assert!(replies[0].contains("ReverseStep+"));
+        assert_eq!(replies[1], format!("m{}", &xml[..0x10]));
+        assert_eq!(replies[2], format!("l{}", &xml[0x10..]));
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"16\" type=\"code_ptr\"/>"));
+    }
+}
@@ -0,0 +1,203 @@
+use super::analysis::{self, Flow};
+use super::constants::RAM_OFFSET;
+use super::decompiler::{self, decompile_word};
+use super::emulator::Chip8Error;
+
+use std::collections::BTreeSet;
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum EdgeKind {
+    // falling through into the next block
+    Next,
+    Jump,
+    // the branch a skip instruction takes when it does skip
+    Skip,
+    Call,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Edge {
+    pub kind: EdgeKind,
+    pub target: u16,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct BasicBlock {
+    pub start: u16,
+    // the address of every instruction in the block, in order
+    pub instructions: Vec<u16>,
+    pub edges: Vec<Edge>,
+}
+
+pub fn graph_rom(path: String) -> Result<String, Chip8Error> {
+    let rom = decompiler::read_rom(path)?;
+    Ok(to_dot(&rom, &basic_blocks(&rom)))
+}
+
+// split the reachable code of a ROM into basic blocks, which start at the entry point, at every
+// branch target and after every branch
+pub fn basic_blocks(rom: &[u8]) -> Vec<BasicBlock> {
+    let code = analysis::analyse(rom).code;
+    let code_at = |address: u16| &rom[(address - RAM_OFFSET) as usize..];
+    let is_code = |address: u16| {
+        address
+            .checked_sub(RAM_OFFSET)
+            .is_some_and(|offset| code.get(offset as usize) == Some(&true))
+    };
+
+    let mut leaders = BTreeSet::new();
+    leaders.insert(RAM_OFFSET);
+    for address in (0..code.len())
+        .filter(|&i| code[i])
+        .map(|i| RAM_OFFSET + i as u16)
+    {
+        let (next, skip) = analysis::successors(code_at(address), address);
+        match analysis::flow(code_at(address)) {
+            Flow::Next => continue,
+            Flow::Jump(target) => {
+                leaders.insert(target);
+            }
+            Flow::Call(target) => {
+                leaders.insert(target);
+            }
+            Flow::Skip => {
+                leaders.insert(skip);
+            }
+            Flow::Return | Flow::Stop => (),
+        }
+        leaders.insert(next);
+    }
+
+    let mut blocks = Vec::new();
+    for &start in leaders.iter().filter(|&&leader| is_code(leader)) {
+        let mut block = BasicBlock {
+            start,
+            instructions: Vec::new(),
+            edges: Vec::new(),
+        };
+        let mut address = start;
+        loop {
+            block.instructions.push(address);
+            let (next, skip) = analysis::successors(code_at(address), address);
+            let edges = match analysis::flow(code_at(address)) {
+                Flow::Next if is_code(next) && !leaders.contains(&next) => {
+                    address = next;
+                    continue;
+                }
+                Flow::Next => vec![(EdgeKind::Next, next)],
+                Flow::Jump(target) => vec![(EdgeKind::Jump, target)],
+                Flow::Call(target) => vec![(EdgeKind::Call, target), (EdgeKind::Next, next)],
+                Flow::Skip => vec![(EdgeKind::Next, next), (EdgeKind::Skip, skip)],
+                Flow::Return | Flow::Stop => vec![],
+            };
+            block.edges = edges
+                .into_iter()
+                .filter(|&(_, target)| is_code(target))
+                .map(|(kind, target)| Edge { kind, target })
+                .collect();
+            break;
+        }
+        blocks.push(block);
+    }
+    blocks
+}
+
+pub fn to_dot(rom: &[u8], blocks: &[BasicBlock]) -> String {
+    let mut dot = vec![
+        "digraph cfg {".to_string(),
+        "    node [shape=box, fontname=\"monospace\"];".to_string(),
+    ];
+
+    for block in blocks {
+        let mut label = String::new();
+        for &address in &block.instructions {
+            let offset = (address - RAM_OFFSET) as usize;
+            let text = decompile_word(&rom[offset..]);
+            label += &format!("{:#06x}  {}\\l", address, text);
+        }
+        dot.push(format!("    b_{:03X} [label=\"{}\"];", block.start, label));
+    }
+    for block in blocks {
+        for edge in &block.edges {
+            let attributes = match edge.kind {
+                EdgeKind::Next | EdgeKind::Jump => "",
+                EdgeKind::Skip => " [label=\"skip\"]",
+                EdgeKind::Call => " [label=\"call\", style=dashed]",
+            };
+            dot.push(format!(
+                "    b_{:03X} -> b_{:03X}{};",
+                block.start, edge.target, attributes
+            ));
+        }
+    }
+
+    dot.push("}".to_string());
+    dot.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROM: [u8; 14] = [
+        0x60, 0x00, // 0x200 LD   v0,  0x00
+        0x22, 0x0a, // 0x202 CALL 0x020a
+        0x30, 0x05, // 0x204 SE   v0,  0x05
+        0x12, 0x02, // 0x206 JP   0x0202
+        0x12, 0x08, // 0x208 JP   0x0208
+        0x70, 0x01, // 0x20a ADD  v0,  0x01
+        0x00, 0xee, // 0x20c RET
+    ];
+
+    #[test]
+    fn test_basic_blocks() {
+        let blocks = basic_blocks(&ROM);
+        let edge = |kind, target| Edge { kind, target };
+        assert_eq!(
+            blocks,
+            vec![
+                BasicBlock {
+                    start: 0x200,
+                    instructions: vec![0x200],
+                    edges: vec![edge(EdgeKind::Next, 0x202)],
+                },
+                BasicBlock {
+                    start: 0x202,
+                    instructions: vec![0x202],
+                    edges: vec![edge(EdgeKind::Call, 0x20a), edge(EdgeKind::Next, 0x204)],
+                },
+                BasicBlock {
+                    start: 0x204,
+                    instructions: vec![0x204],
+                    edges: vec![edge(EdgeKind::Next, 0x206), edge(EdgeKind::Skip, 0x208)],
+                },
+                BasicBlock {
+                    start: 0x206,
+                    instructions: vec![0x206],
+                    edges: vec![edge(EdgeKind::Jump, 0x202)],
+                },
+                BasicBlock {
+                    start: 0x208,
+                    instructions: vec![0x208],
+                    edges: vec![edge(EdgeKind::Jump, 0x208)],
+                },
+                BasicBlock {
+                    start: 0x20a,
+                    instructions: vec![0x20a, 0x20c],
+                    edges: vec![],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_dot() {
+        let dot = to_dot(&ROM, &basic_blocks(&ROM));
+        assert!(dot.starts_with("digraph cfg {"));
+        assert!(dot.contains("    b_20A [label=\"0x020a  ADD  v0,  0x01\\l0x020c  RET\\l\"];"));
+        assert!(dot.contains("    b_202 -> b_20A [label=\"call\", style=dashed];"));
+        assert!(dot.contains("    b_204 -> b_208 [label=\"skip\"];"));
+        assert!(dot.contains("    b_206 -> b_202;"));
+        assert!(dot.ends_with("}"));
+    }
+}
@@ -0,0 +1,376 @@
+use std::fmt;
+
+pub type Address = u16;
+pub type Register = u8;
+
+// every instruction the emulator understands, named after Cowgod's reference
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Instruction {
+    Cls,
+    Ret,
+    Jp(Address),
+    Call(Address),
+    SeByte(Register, u8),
+    SneByte(Register, u8),
+    SeReg(Register, Register),
+    LdByte(Register, u8),
+    AddByte(Register, u8),
+    LdReg(Register, Register),
+    Or(Register, Register),
+    And(Register, Register),
+    Xor(Register, Register),
+    AddReg(Register, Register),
+    Sub(Register, Register),
+    // the shifts ignore vy, but keep it so that encoding gives back the original word
+    Shr(Register, Register),
+    Subn(Register, Register),
+    Shl(Register, Register),
+    SneReg(Register, Register),
+    LdI(Address),
+    JpV0(Address),
+    Rnd(Register, u8),
+    Drw(Register, Register, u8),
+    Skp(Register),
+    Sknp(Register),
+    LdVxDt(Register),
+    LdK(Register),
+    LdDtVx(Register),
+    LdStVx(Register),
+    AddI(Register),
+    LdF(Register),
+    LdB(Register),
+    // LD [I], vx
+    LdStore(Register),
+    // LD vx, [I]
+    LdRead(Register),
+    // the SUPER-CHIP additions
+    Scd(u8),
+    Scr,
+    Scl,
+    Exit,
+    Low,
+    High,
+    LdHf(Register),
+    // LD R, vx saves registers to the HP48's RPL user flags, LD vx, R restores them
+    LdRVx(Register),
+    LdVxR(Register),
+    // the XO-CHIP additions
+    Scu(u8),
+    // LD I, LONG addr is followed by a whole word of address, making it 4 bytes long
+    LdILong(u16),
+    // LD [I], vx-vy and LD vx-vy, [I] save and load a range of registers, leaving I alone
+    SaveRange(Register, Register),
+    LoadRange(Register, Register),
+    Plane(u8),
+    Audio,
+    LdPitch(Register),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum DecodeError {
+    Unrecognized(u16),
+    // there were fewer bytes left than the word or address the instruction needs
+    Truncated(usize),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::Unrecognized(opcode) => write!(f, "unrecognized opcode {:#06x}", opcode),
+            DecodeError::Truncated(length) => {
+                write!(
+                    f,
+                    "only {} bytes left to decode an instruction from",
+                    length
+                )
+            }
+        }
+    }
+}
+
+// the size in bytes of the instruction at the start of `code`
+pub fn size_at(code: &[u8]) -> usize {
+    if code.starts_with(&[0xf0, 0x00]) {
+        4
+    } else {
+        2
+    }
+}
+
+// decode the instruction at the start of `code`, which needs to hold the address that follows a
+// LD I, LONG as well as the word itself
+pub fn decode_at(code: &[u8]) -> Result<Instruction, DecodeError> {
+    match code {
+        [0xf0, 0x00, upper, lower, ..] => {
+            Ok(Instruction::LdILong(u16::from_be_bytes([*upper, *lower])))
+        }
+        [high, low, ..] => decode(u16::from_be_bytes([*high, *low])),
+        _ => Err(DecodeError::Truncated(code.len())),
+    }
+}
+
+// decode a single word, which can't be a LD I, LONG
+pub fn decode(opcode: u16) -> Result<Instruction, DecodeError> {
+    use Instruction::*;
+
+    let nnn = opcode & 0x0fff;
+    let kk = opcode as u8;
+    let x = ((opcode >> 8) & 0xf) as u8;
+    let y = ((opcode >> 4) & 0xf) as u8;
+    let n = (opcode & 0xf) as u8;
+    let instruction = match (opcode >> 12, x, y, n) {
+        (0x0, 0x0, 0xe, 0x0) => Cls,
+        (0x0, 0x0, 0xe, 0xe) => Ret,
+        (0x0, 0x0, 0xc, _) => Scd(n),
+        (0x0, 0x0, 0xd, _) => Scu(n),
+        (0x0, 0x0, 0xf, 0xb) => Scr,
+        (0x0, 0x0, 0xf, 0xc) => Scl,
+        (0x0, 0x0, 0xf, 0xd) => Exit,
+        (0x0, 0x0, 0xf, 0xe) => Low,
+        (0x0, 0x0, 0xf, 0xf) => High,
+        (0x1, _, _, _) => Jp(nnn),
+        (0x2, _, _, _) => Call(nnn),
+        (0x3, _, _, _) => SeByte(x, kk),
+        (0x4, _, _, _) => SneByte(x, kk),
+        (0x5, _, _, 0x0) => SeReg(x, y),
+        (0x5, _, _, 0x2) => SaveRange(x, y),
+        (0x5, _, _, 0x3) => LoadRange(x, y),
+        (0x6, _, _, _) => LdByte(x, kk),
+        (0x7, _, _, _) => AddByte(x, kk),
+        (0x8, _, _, 0x0) => LdReg(x, y),
+        (0x8, _, _, 0x1) => Or(x, y),
+        (0x8, _, _, 0x2) => And(x, y),
+        (0x8, _, _, 0x3) => Xor(x, y),
+        (0x8, _, _, 0x4) => AddReg(x, y),
+        (0x8, _, _, 0x5) => Sub(x, y),
+        (0x8, _, _, 0x6) => Shr(x, y),
+        (0x8, _, _, 0x7) => Subn(x, y),
+        (0x8, _, _, 0xe) => Shl(x, y),
+        (0x9, _, _, 0x0) => SneReg(x, y),
+        (0xa, _, _, _) => LdI(nnn),
+        (0xb, _, _, _) => JpV0(nnn),
+        (0xc, _, _, _) => Rnd(x, kk),
+        (0xd, _, _, _) => Drw(x, y, n),
+        (0xe, _, 0x9, 0xe) => Skp(x),
+        (0xe, _, 0xa, 0x1) => Sknp(x),
+        (0xf, _, 0x0, 0x7) => LdVxDt(x),
+        (0xf, _, 0x0, 0xa) => LdK(x),
+        (0xf, _, 0x1, 0x5) => LdDtVx(x),
+        (0xf, _, 0x1, 0x8) => LdStVx(x),
+        (0xf, _, 0x1, 0xe) => AddI(x),
+        (0xf, _, 0x2, 0x9) => LdF(x),
+        (0xf, _, 0x3, 0x3) => LdB(x),
+        (0xf, _, 0x5, 0x5) => LdStore(x),
+        (0xf, _, 0x6, 0x5) => LdRead(x),
+        (0xf, _, 0x3, 0x0) => LdHf(x),
+        (0xf, _, 0x7, 0x5) => LdRVx(x),
+        (0xf, _, 0x8, 0x5) => LdVxR(x),
+        (0xf, _, 0x0, 0x1) => Plane(x),
+        (0xf, 0x0, 0x0, 0x2) => Audio,
+        (0xf, _, 0x3, 0xa) => LdPitch(x),
+        (_, _, _, _) => return Err(DecodeError::Unrecognized(opcode)),
+    };
+    Ok(instruction)
+}
+
+// the bytes of an instruction, which are a single word for all but LD I, LONG
+pub fn encode(instruction: Instruction) -> Vec<u8> {
+    use Instruction::*;
+
+    let x = |x: Register| (x as u16 & 0xf) << 8;
+    let y = |y: Register| (y as u16 & 0xf) << 4;
+    let word = match instruction {
+        Cls => 0x00e0,
+        Ret => 0x00ee,
+        Jp(a) => 0x1000 | (a & 0x0fff),
+        Call(a) => 0x2000 | (a & 0x0fff),
+        SeByte(vx, k) => 0x3000 | x(vx) | k as u16,
+        SneByte(vx, k) => 0x4000 | x(vx) | k as u16,
+        SeReg(vx, vy) => 0x5000 | x(vx) | y(vy),
+        LdByte(vx, k) => 0x6000 | x(vx) | k as u16,
+        AddByte(vx, k) => 0x7000 | x(vx) | k as u16,
+        LdReg(vx, vy) => 0x8000 | x(vx) | y(vy),
+        Or(vx, vy) => 0x8001 | x(vx) | y(vy),
+        And(vx, vy) => 0x8002 | x(vx) | y(vy),
+        Xor(vx, vy) => 0x8003 | x(vx) | y(vy),
+        AddReg(vx, vy) => 0x8004 | x(vx) | y(vy),
+        Sub(vx, vy) => 0x8005 | x(vx) | y(vy),
+        Shr(vx, vy) => 0x8006 | x(vx) | y(vy),
+        Subn(vx, vy) => 0x8007 | x(vx) | y(vy),
+        Shl(vx, vy) => 0x800e | x(vx) | y(vy),
+        SneReg(vx, vy) => 0x9000 | x(vx) | y(vy),
+        LdI(a) => 0xa000 | (a & 0x0fff),
+        JpV0(a) => 0xb000 | (a & 0x0fff),
+        Rnd(vx, k) => 0xc000 | x(vx) | k as u16,
+        Drw(vx, vy, n) => 0xd000 | x(vx) | y(vy) | (n as u16 & 0xf),
+        Skp(vx) => 0xe09e | x(vx),
+        Sknp(vx) => 0xe0a1 | x(vx),
+        LdVxDt(vx) => 0xf007 | x(vx),
+        LdK(vx) => 0xf00a | x(vx),
+        LdDtVx(vx) => 0xf015 | x(vx),
+        LdStVx(vx) => 0xf018 | x(vx),
+        AddI(vx) => 0xf01e | x(vx),
+        LdF(vx) => 0xf029 | x(vx),
+        LdB(vx) => 0xf033 | x(vx),
+        LdStore(vx) => 0xf055 | x(vx),
+        LdRead(vx) => 0xf065 | x(vx),
+        Scd(n) => 0x00c0 | (n as u16 & 0xf),
+        Scr => 0x00fb,
+        Scl => 0x00fc,
+        Exit => 0x00fd,
+        Low => 0x00fe,
+        High => 0x00ff,
+        LdHf(vx) => 0xf030 | x(vx),
+        LdRVx(vx) => 0xf075 | x(vx),
+        LdVxR(vx) => 0xf085 | x(vx),
+        Scu(n) => 0x00d0 | (n as u16 & 0xf),
+        LdILong(a) => return vec![0xf0, 0x00, (a >> 8) as u8, a as u8],
+        SaveRange(vx, vy) => 0x5002 | x(vx) | y(vy),
+        LoadRange(vx, vy) => 0x5003 | x(vx) | y(vy),
+        Plane(n) => 0xf001 | x(n),
+        Audio => 0xf002,
+        LdPitch(vx) => 0xf03a | x(vx),
+    };
+    word.to_be_bytes().to_vec()
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use Instruction::*;
+
+        match *self {
+            Cls => write!(f, "CLS"),
+            Ret => write!(f, "RET"),
+            Jp(a) => write!(f, "JP   {:#06x}", a),
+            Call(a) => write!(f, "CALL {:#06x}", a),
+            SeByte(vx, k) => write!(f, "SE   v{:X},  {:#04x}", vx, k),
+            SneByte(vx, k) => write!(f, "SNE  v{:X},  {:#04x}", vx, k),
+            SeReg(vx, vy) => write!(f, "SE   v{:X},  v{:X}", vx, vy),
+            LdByte(vx, k) => write!(f, "LD   v{:X},  {:#04x}", vx, k),
+            AddByte(vx, k) => write!(f, "ADD  v{:X},  {:#04x}", vx, k),
+            LdReg(vx, vy) => write!(f, "LD   v{:X},  v{:X}", vx, vy),
+            Or(vx, vy) => write!(f, "OR   v{:X},  v{:X}", vx, vy),
+            And(vx, vy) => write!(f, "AND  v{:X},  v{:X}", vx, vy),
+            Xor(vx, vy) => write!(f, "XOR  v{:X},  v{:X}", vx, vy),
+            AddReg(vx, vy) => write!(f, "ADD  v{:X},  v{:X}", vx, vy),
+            Sub(vx, vy) => write!(f, "SUB  v{:X},  v{:X}", vx, vy),
+            Shr(vx, vy) => write!(f, "SHR  v{:X},  v{:X}", vx, vy),
+            Subn(vx, vy) => write!(f, "SUBN v{:X},  v{:X}", vx, vy),
+            Shl(vx, vy) => write!(f, "SHL  v{:X},  v{:X}", vx, vy),
+            SneReg(vx, vy) => write!(f, "SNE  v{:X},  v{:X}", vx, vy),
+            LdI(a) => write!(f, "LD   I,   {:#06x}", a),
+            JpV0(a) => write!(f, "JP   V0,  {:#06x}", a),
+            Rnd(vx, k) => write!(f, "RND  v{:X},  {:#04x}", vx, k),
+            Drw(vx, vy, n) => write!(f, "DRW  v{:X},  v{:X}, {:#03x}", vx, vy, n),
+            Skp(vx) => write!(f, "SKP  v{:X}", vx),
+            Sknp(vx) => write!(f, "SKNP v{:X}", vx),
+            LdVxDt(vx) => write!(f, "LD   v{:X},  DT", vx),
+            LdK(vx) => write!(f, "LD   v{:X},  K", vx),
+            LdDtVx(vx) => write!(f, "LD   DT,  v{:X}", vx),
+            LdStVx(vx) => write!(f, "LD   ST,  v{:X}", vx),
+            AddI(vx) => write!(f, "ADD  I,   v{:X}", vx),
+            LdF(vx) => write!(f, "LD   F,   v{:X}", vx),
+            LdB(vx) => write!(f, "LD   B,   v{:X}", vx),
+            LdStore(vx) => write!(f, "LD   [I], v{:X}", vx),
+            LdRead(vx) => write!(f, "LD   v{:X},  [I]", vx),
+            Scd(n) => write!(f, "SCD  {:#03x}", n),
+            Scr => write!(f, "SCR"),
+            Scl => write!(f, "SCL"),
+            Exit => write!(f, "EXIT"),
+            Low => write!(f, "LOW"),
+            High => write!(f, "HIGH"),
+            LdHf(vx) => write!(f, "LD   HF,  v{:X}", vx),
+            LdRVx(vx) => write!(f, "LD   R,   v{:X}", vx),
+            LdVxR(vx) => write!(f, "LD   v{:X},  R", vx),
+            Scu(n) => write!(f, "SCU  {:#03x}", n),
+            LdILong(a) => write!(f, "LD   I,   LONG {:#06x}", a),
+            SaveRange(vx, vy) => write!(f, "LD   [I], v{:X}-v{:X}", vx, vy),
+            LoadRange(vx, vy) => write!(f, "LD   v{:X}-v{:X}, [I]", vx, vy),
+            Plane(n) => write!(f, "PLANE {:#03x}", n),
+            Audio => write!(f, "AUDIO"),
+            LdPitch(vx) => write!(f, "LD   PITCH, v{:X}", vx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(decode(0x00e0), Ok(Instruction::Cls));
+        assert_eq!(decode(0x1234), Ok(Instruction::Jp(0x234)));
+        assert_eq!(decode(0x3a56), Ok(Instruction::SeByte(0xa, 0x56)));
+        assert_eq!(decode(0x8e36), Ok(Instruction::Shr(0xe, 0x3)));
+        assert_eq!(decode(0xd123), Ok(Instruction::Drw(0x1, 0x2, 0x3)));
+        assert_eq!(decode(0xf265), Ok(Instruction::LdRead(0x2)));
+        assert_eq!(decode(0x00c4), Ok(Instruction::Scd(0x4)));
+        assert_eq!(decode(0x00ff), Ok(Instruction::High));
+        assert_eq!(decode(0xf330), Ok(Instruction::LdHf(0x3)));
+        assert_eq!(decode(0xf785), Ok(Instruction::LdVxR(0x7)));
+        assert_eq!(decode(0x5122), Ok(Instruction::SaveRange(0x1, 0x2)));
+        assert_eq!(decode(0xf201), Ok(Instruction::Plane(0x2)));
+        assert_eq!(decode(0xf000), Err(DecodeError::Unrecognized(0xf000)));
+        assert_eq!(decode(0xf102), Err(DecodeError::Unrecognized(0xf102)));
+        assert_eq!(decode(0x0123), Err(DecodeError::Unrecognized(0x0123)));
+        assert_eq!(decode(0x800f), Err(DecodeError::Unrecognized(0x800f)));
+        assert_eq!(decode(0xe0ff), Err(DecodeError::Unrecognized(0xe0ff)));
+    }
+
+    #[test]
+    fn test_decode_at() {
+        assert_eq!(
+            decode_at(&[0xf0, 0x00, 0x12, 0x34]),
+            Ok(Instruction::LdILong(0x1234))
+        );
+        assert_eq!(
+            decode_at(&[0xf0, 0x00]),
+            Err(DecodeError::Unrecognized(0xf000))
+        );
+        assert_eq!(decode_at(&[0x00, 0xe0, 0xf0, 0x00]), Ok(Instruction::Cls));
+        assert_eq!(decode_at(&[0x12]), Err(DecodeError::Truncated(1)));
+        assert_eq!(decode_at(&[]), Err(DecodeError::Truncated(0)));
+        assert_eq!(size_at(&[0xf0, 0x00, 0x12, 0x34]), 4);
+        assert_eq!(size_at(&[0x00, 0xe0, 0xf0, 0x00]), 2);
+    }
+
+    #[test]
+    fn test_encode() {
+        for opcode in 0..=0xffff {
+            if let Ok(instruction) = decode(opcode) {
+                assert_eq!(encode(instruction), opcode.to_be_bytes(), "{}", instruction);
+            }
+        }
+        assert_eq!(
+            encode(Instruction::LdILong(0x1234)),
+            [0xf0, 0x00, 0x12, 0x34]
+        );
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(Instruction::Call(0x234).to_string(), "CALL 0x0234");
+        assert_eq!(Instruction::JpV0(0x234).to_string(), "JP   V0,  0x0234");
+        assert_eq!(Instruction::LdByte(0x4, 0x56).to_string(), "LD   v4,  0x56");
+        assert_eq!(
+            Instruction::Drw(0x0, 0xe, 0x3).to_string(),
+            "DRW  v0,  vE, 0x3"
+        );
+        assert_eq!(Instruction::LdStore(0x2).to_string(), "LD   [I], v2");
+        assert_eq!(Instruction::Scd(0xa).to_string(), "SCD  0xa");
+        assert_eq!(Instruction::LdRVx(0x5).to_string(), "LD   R,   v5");
+        assert_eq!(
+            Instruction::LdILong(0x1234).to_string(),
+            "LD   I,   LONG 0x1234"
+        );
+        assert_eq!(
+            Instruction::LoadRange(0x1, 0xa).to_string(),
+            "LD   v1-vA, [I]"
+        );
+        assert_eq!(
+            DecodeError::Unrecognized(0x0123).to_string(),
+            "unrecognized opcode 0x0123"
+        );
+    }
+}
@@ -0,0 +1,116 @@
+// the places where CHIP-8 interpreters disagree about what an instruction does
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    // SHR and SHL shift vy into vx, rather than shifting vx in place
+    pub shift_vy: bool,
+    // what LD [I], vx and LD vx, [I] leave in I
+    pub load_store: LoadStore,
+    // JP V0, addr jumps to addr + vx, where x is the top nibble of addr, rather than addr + v0
+    pub jump_vx: bool,
+    // sprites drawn past the edge of the screen are cut off, rather than wrapping around
+    pub clip_sprites: bool,
+    // OR, AND and XOR set vF to 0
+    pub vf_reset: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoadStore {
+    // I is left pointing past the last register
+    IncrementByXPlusOne,
+    // I is left pointing at the last register
+    IncrementByX,
+    Unchanged,
+}
+
+impl Quirks {
+    // Cowgod's technical reference, which this emulator has always followed: shifts work on vx in
+    // place, I is left alone by loads and stores, and sprites wrap around the edges of the screen
+    pub fn cowgod() -> Quirks {
+        Quirks {
+            shift_vy: false,
+            load_store: LoadStore::Unchanged,
+            jump_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+        }
+    }
+
+    // the original interpreter on the COSMAC VIP
+    pub fn vip() -> Quirks {
+        Quirks {
+            shift_vy: true,
+            load_store: LoadStore::IncrementByXPlusOne,
+            jump_vx: false,
+            clip_sprites: true,
+            vf_reset: true,
+        }
+    }
+
+    // CHIP-48 on the HP-48 calculators
+    pub fn chip48() -> Quirks {
+        Quirks {
+            shift_vy: false,
+            load_store: LoadStore::IncrementByX,
+            jump_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+        }
+    }
+
+    // SUPER-CHIP 1.1, also on the HP-48
+    pub fn schip() -> Quirks {
+        Quirks {
+            shift_vy: false,
+            load_store: LoadStore::Unchanged,
+            jump_vx: true,
+            clip_sprites: true,
+            vf_reset: false,
+        }
+    }
+
+    // XO-CHIP, as Octo runs it
+    pub fn xochip() -> Quirks {
+        Quirks {
+            shift_vy: true,
+            load_store: LoadStore::IncrementByXPlusOne,
+            jump_vx: false,
+            clip_sprites: false,
+            vf_reset: false,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::cowgod()
+    }
+}
+
+impl std::str::FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cowgod" => Ok(Quirks::cowgod()),
+            "vip" => Ok(Quirks::vip()),
+            "chip48" => Ok(Quirks::chip48()),
+            "schip" => Ok(Quirks::schip()),
+            "xochip" => Ok(Quirks::xochip()),
+            _ => Err(format!("unknown quirks preset `{}`", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_str() {
+        assert_eq!("cowgod".parse(), Ok(Quirks::default()));
+        assert_eq!("vip".parse(), Ok(Quirks::vip()));
+        assert_eq!("schip".parse(), Ok(Quirks::schip()));
+        assert_eq!("xochip".parse(), Ok(Quirks::xochip()));
+        assert!("eti660".parse::<Quirks>().is_err());
+    }
+}
@@ -0,0 +1,164 @@
+use super::constants::SCHIP_DISP_H;
+use super::emulator::Chip8Regs;
+use super::instruction::Address;
+
+use std::collections::VecDeque;
+use std::mem;
+
+// both planes of the display
+pub(super) type Vram = [[u128; SCHIP_DISP_H as usize]; 2];
+
+// how an instruction or a tick of the timers found the machine, so that it can be undone. Only
+// what it changed of memory and the display is kept, as they're too big to copy every time
+pub(super) struct Record {
+    pub(super) registers: Chip8Regs,
+    pub(super) keyboard: u16,
+    pub(super) stack: [u16; 16],
+    // the bytes the instruction wrote over, in the order it wrote them
+    pub(super) ram: Vec<(Address, u8)>,
+    pub(super) vram: Option<Box<Vram>>,
+    pub(super) hires: bool,
+    pub(super) planes: u8,
+    pub(super) rpl: [u8; 16],
+    pub(super) audio_pattern: Option<[u8; 16]>,
+    pub(super) pitch: u8,
+    pub(super) draw_flag: bool,
+    pub(super) exited: bool,
+    // whether this is the tick of the timers at the end of a frame, rather than an instruction
+    pub(super) frame: bool,
+}
+
+impl Record {
+    // roughly how much memory it takes up, in bytes
+    fn size(&self) -> usize {
+        let vram = match self.vram {
+            Some(_) => mem::size_of::<Vram>(),
+            None => 0,
+        };
+        mem::size_of::<Record>() + self.ram.capacity() * mem::size_of::<(Address, u8)>() + vram
+    }
+}
+
+// the most recent records, to step back through, with the oldest forgotten once there are more
+// instructions than the depth or they take up more than the budget
+pub struct History {
+    records: VecDeque<Record>,
+    // in instructions
+    depth: usize,
+    // in bytes
+    budget: usize,
+    instructions: usize,
+    size: usize,
+}
+
+impl History {
+    pub fn new(depth: usize, budget: usize) -> History {
+        History {
+            records: VecDeque::new(),
+            depth,
+            budget,
+            instructions: 0,
+            size: 0,
+        }
+    }
+
+    // how many instructions can be stepped back through
+    pub fn instructions(&self) -> usize {
+        self.instructions
+    }
+
+    // roughly how much memory the history takes up, in bytes
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+        self.instructions = 0;
+        self.size = 0;
+    }
+
+    pub(super) fn push(&mut self, record: Record) {
+        self.instructions += !record.frame as usize;
+        self.size += record.size();
+        self.records.push_back(record);
+        while self.instructions > self.depth || self.size > self.budget {
+            match self.records.pop_front() {
+                Some(oldest) => self.forget(&oldest),
+                None => break,
+            }
+        }
+    }
+
+    pub(super) fn pop(&mut self) -> Option<Record> {
+        let record = self.records.pop_back()?;
+        self.forget(&record);
+        Some(record)
+    }
+
+    // whether the most recent record is a tick of the timers
+    pub(super) fn at_frame(&self) -> bool {
+        matches!(self.records.back(), Some(record) if record.frame)
+    }
+
+    fn forget(&mut self, record: &Record) {
+        self.instructions -= !record.frame as usize;
+        self.size -= record.size();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(frame: bool) -> Record {
+        Record {
+            registers: Chip8Regs::default(),
+            keyboard: 0,
+            stack: [0; 16],
+            ram: Vec::new(),
+            vram: None,
+            hires: false,
+            planes: 0b01,
+            rpl: [0; 16],
+            audio_pattern: None,
+            pitch: 64,
+            draw_flag: false,
+            exited: false,
+            frame,
+        }
+    }
+
+    #[test]
+    fn test_depth() {
+        let mut history = History::new(2, usize::MAX);
+        for _ in 0..3 {
+            history.push(record(false));
+        }
+        history.push(record(true));
+        // ticks of the timers don't count towards the depth
+        assert_eq!(history.instructions(), 2);
+        assert!(history.at_frame());
+        assert!(history.pop().unwrap().frame);
+        assert!(!history.pop().unwrap().frame);
+        assert_eq!(history.instructions(), 1);
+        history.clear();
+        assert!(history.pop().is_none());
+        assert_eq!(history.size(), 0);
+    }
+
+    #[test]
+    fn test_budget() {
+        let size = record(false).size();
+        let mut history = History::new(100, 2 * size);
+        for _ in 0..3 {
+            history.push(record(false));
+        }
+        assert_eq!((history.instructions(), history.size()), (2, 2 * size));
+        let mut drawn = record(false);
+        drawn.vram = Some(Box::new([[0; SCHIP_DISP_H as usize]; 2]));
+        history.push(drawn);
+        // it's over the budget on its own
+        assert_eq!((history.instructions(), history.size()), (0, 0));
+    }
+}
@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+// the most frames we'll run back to back to catch up, e.g. after the host was suspended. Any more
+// than that and we give up on the lost time rather than fast forwarding through it
+const MAX_CATCH_UP: u32 = 5;
+
+// keeps emulated frames in step with the wall clock, however fast the host is
+pub struct Scheduler {
+    frame_time: Duration,
+    last: Instant,
+    // time that has passed but hasn't been emulated yet
+    lag: Duration,
+}
+
+impl Scheduler {
+    pub fn new(frame_rate: u32, now: Instant) -> Scheduler {
+        Scheduler {
+            frame_time: Duration::from_secs(1) / frame_rate,
+            last: now,
+            lag: Duration::from_secs(0),
+        }
+    }
+
+    // how many frames should be emulated to catch up to `now`
+    pub fn frames_due(&mut self, now: Instant) -> u32 {
+        self.lag += now.saturating_duration_since(self.last);
+        self.last = now;
+
+        let mut frames = 0;
+        while self.lag >= self.frame_time {
+            self.lag -= self.frame_time;
+            frames += 1;
+        }
+        if frames > MAX_CATCH_UP {
+            frames = MAX_CATCH_UP;
+        }
+        frames
+    }
+
+    // how long until the next frame is due
+    pub fn until_next_frame(&self) -> Duration {
+        self.frame_time - self.lag
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_due() {
+        let start = Instant::now();
+        let frame = Duration::from_secs(1) / 60;
+        let mut scheduler = Scheduler::new(60, start);
+
+        assert_eq!(scheduler.frames_due(start), 0);
+        assert_eq!(scheduler.until_next_frame(), frame);
+        assert_eq!(scheduler.frames_due(start + frame / 2), 0);
+        assert_eq!(scheduler.frames_due(start + frame), 1);
+        assert_eq!(scheduler.frames_due(start + frame * 3 + frame / 2), 2);
+        assert_eq!(scheduler.until_next_frame(), frame / 2);
+
+        // a second's worth of frames in half frame steps
+        let mut frames = 0;
+        let mut now = start + frame * 4;
+        scheduler = Scheduler::new(60, now);
+        for _ in 0..120 {
+            now += frame / 2;
+            frames += scheduler.frames_due(now);
+        }
+        assert_eq!(frames, 60);
+    }
+
+    #[test]
+    fn test_frames_due_after_stall() {
+        let start = Instant::now();
+        let mut scheduler = Scheduler::new(60, start);
+        assert_eq!(
+            scheduler.frames_due(start + Duration::from_secs(10)),
+            MAX_CATCH_UP
+        );
+        assert_eq!(scheduler.frames_due(start + Duration::from_secs(10)), 0);
+    }
+}
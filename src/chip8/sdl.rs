@@ -0,0 +1,230 @@
+use super::audio::{ToneGenerator, ToneSettings};
+use super::constants::{CELL_H, CELL_W, SCHIP_DISP_H, SCHIP_DISP_W};
+use super::emulator::{Chip8, Chip8Error};
+use super::frontend::Frontend;
+
+use std::convert::TryInto;
+
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::EventPump;
+
+// the colour of a pixel, indexed by the planes it's set in: neither, the first, the second, both
+const PALETTE: [Color; 4] = [
+    Color::RGB(0, 0, 0),
+    Color::RGB(255, 255, 255),
+    Color::RGB(255, 102, 0),
+    Color::RGB(102, 34, 0),
+];
+
+const SAMPLE_RATE: i32 = 44100;
+
+// a window for the display, the keyboard for the keypad and the default audio device for the tone
+pub struct SdlFrontend {
+    canvas: Canvas<Window>,
+    event_pump: EventPump,
+    keys: u16,
+    rewinding: bool,
+    // None when there's no audio device to play on, in which case the emulator runs silently
+    audio: Option<AudioDevice<Tone>>,
+}
+
+struct Tone(ToneGenerator);
+
+impl AudioCallback for Tone {
+    type Channel = f32;
+
+    fn callback(&mut self, samples: &mut [f32]) {
+        self.0.fill(samples);
+    }
+}
+
+impl SdlFrontend {
+    pub fn new() -> SdlFrontend {
+        SdlFrontend::with_tone(ToneSettings::default())
+    }
+
+    pub fn with_tone(tone: ToneSettings) -> SdlFrontend {
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+
+        let window = video_subsystem
+            .window(
+                "Chip-8 Emulator",
+                CELL_W * (SCHIP_DISP_W),
+                CELL_H * (SCHIP_DISP_H),
+            )
+            .position_centered()
+            .build()
+            .unwrap();
+
+        let desired = AudioSpecDesired {
+            freq: Some(SAMPLE_RATE),
+            channels: Some(1),
+            samples: None,
+        };
+        let audio = sdl_context.audio().and_then(|audio| {
+            audio.open_playback(None, &desired, |spec| {
+                Tone(ToneGenerator::new(tone, spec.freq as u32))
+            })
+        });
+        let audio = match audio {
+            Ok(device) => {
+                device.resume();
+                Some(device)
+            }
+            Err(e) => {
+                eprintln!("No sound: {}", e);
+                None
+            }
+        };
+
+        SdlFrontend {
+            canvas: window.into_canvas().build().unwrap(),
+            event_pump: sdl_context.event_pump().unwrap(),
+            keys: 0,
+            rewinding: false,
+            audio,
+        }
+    }
+}
+
+impl Default for SdlFrontend {
+    fn default() -> Self {
+        SdlFrontend::new()
+    }
+}
+
+impl Frontend for SdlFrontend {
+    fn present(&mut self, planes: [&[u128]; 2]) {
+        self.canvas.set_draw_color(PALETTE[0]);
+        self.canvas.clear();
+
+        // the window fits the hi-res display, so lo-res pixels take up 2x2 cells
+        let height = planes[0].len() as u32;
+        let (cell_w, cell_h) = (
+            CELL_W * SCHIP_DISP_H / height,
+            CELL_H * SCHIP_DISP_H / height,
+        );
+        for (y, (first, second)) in planes[0].iter().zip(planes[1]).enumerate() {
+            for x in 0..height * 2 {
+                let colour = ((first >> x) & 1) | (((second >> x) & 1) << 1);
+                if colour != 0 {
+                    self.canvas.set_draw_color(PALETTE[colour as usize]);
+                    self.canvas
+                        .fill_rect(Rect::new(
+                            (x * cell_w).try_into().unwrap(),
+                            (y as u32 * cell_h).try_into().unwrap(),
+                            cell_w,
+                            cell_h,
+                        ))
+                        .unwrap();
+                }
+            }
+        }
+
+        self.canvas.present();
+    }
+
+    fn poll_input(&mut self) -> Option<u16> {
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => return None,
+                // M isn't on the keypad, so it's free to mute and unmute the tone
+                Event::KeyDown {
+                    keycode: Some(Keycode::M),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(audio) = &mut self.audio {
+                        audio.lock().0.toggle_muted();
+                    }
+                }
+                // and Backspace, held down, rewinds
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => self.rewinding = true,
+                Event::KeyUp {
+                    keycode: Some(Keycode::Backspace),
+                    ..
+                } => self.rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    ..
+                } => self.keys |= keypad_bit(keycode),
+                Event::KeyUp {
+                    keycode: Some(keycode),
+                    ..
+                } => self.keys &= !keypad_bit(keycode),
+                _ => (),
+            }
+        }
+        Some(self.keys)
+    }
+
+    fn rewinding(&self) -> bool {
+        self.rewinding
+    }
+
+    fn start_tone(&mut self) {
+        if let Some(audio) = &mut self.audio {
+            audio.lock().0.start();
+        }
+    }
+
+    fn stop_tone(&mut self) {
+        if let Some(audio) = &mut self.audio {
+            audio.lock().0.stop();
+        }
+    }
+
+    fn set_pattern(&mut self, pattern: Option<&[u8; 16]>, pitch: u8) {
+        if let Some(audio) = &mut self.audio {
+            audio.lock().0.set_pattern(pattern, pitch);
+        }
+    }
+}
+
+// the left hand side of a QWERTY keyboard, laid out like the COSMAC VIP's keypad:
+//   1 2 3 4      1 2 3 C
+//   Q W E R  ->  4 5 6 D
+//   A S D F      7 8 9 E
+//   Z X C V      A 0 B F
+fn keypad_bit(keycode: Keycode) -> u16 {
+    let key = match keycode {
+        Keycode::Num1 => 0x1,
+        Keycode::Num2 => 0x2,
+        Keycode::Num3 => 0x3,
+        Keycode::Num4 => 0xc,
+        Keycode::Q => 0x4,
+        Keycode::W => 0x5,
+        Keycode::E => 0x6,
+        Keycode::R => 0xd,
+        Keycode::A => 0x7,
+        Keycode::S => 0x8,
+        Keycode::D => 0x9,
+        Keycode::F => 0xe,
+        Keycode::Z => 0xa,
+        Keycode::X => 0x0,
+        Keycode::C => 0xb,
+        Keycode::V => 0xf,
+        _ => return 0,
+    };
+    1 << key
+}
+
+impl Chip8 {
+    pub fn run(&mut self) -> Result<(), Chip8Error> {
+        self.run_with(&mut SdlFrontend::new())
+    }
+}
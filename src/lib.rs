@@ -0,0 +1,11 @@
+mod chip8;
+
+pub use chip8::emulator::{Chip8, Chip8Error, FaultAction, FaultPolicy};
+pub use chip8::frontend::Frontend;
+pub use chip8::quirks::Quirks;
+#[cfg(feature = "sdl")]
+pub use chip8::sdl;
+pub use chip8::{
+    analysis, assembler, audio, breakpoint, constants, dap, debugger, decompiler, emulator,
+    frontend, gdb, graph, instruction, quirks, rewind, scheduler,
+};
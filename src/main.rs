@@ -1,8 +1,32 @@
-mod chip8;
+#[cfg(feature = "sdl")]
+use chip8::audio::{ToneSettings, Waveform};
+#[cfg(feature = "sdl")]
+use chip8::constants::{REWIND_BUDGET, REWIND_DEPTH};
+#[cfg(feature = "sdl")]
+use chip8::dap::{self, DapServer};
+#[cfg(feature = "sdl")]
+use chip8::debugger::Debugger;
+#[cfg(feature = "sdl")]
+use chip8::emulator::{self, FaultAction, FaultPolicy};
+#[cfg(feature = "sdl")]
+use chip8::gdb::GdbStub;
+#[cfg(feature = "sdl")]
+use chip8::sdl::SdlFrontend;
+#[cfg(feature = "sdl")]
+use chip8::Quirks;
+use chip8::{assembler, decompiler, graph};
 
-use chip8::{decompiler, emulator};
+use std::fs;
+#[cfg(feature = "sdl")]
+use std::io::{self, BufReader};
+#[cfg(feature = "sdl")]
+use std::net::TcpListener;
+use std::path::Path;
+use std::process;
 
-use clap::{App, Arg};
+#[cfg(feature = "sdl")]
+use clap::value_t;
+use clap::{App, Arg, ArgMatches};
 
 pub fn main() {
     let matches = App::new("Chip-8 Emulator")
@@ -10,7 +34,7 @@ pub fn main() {
         .arg(
             Arg::with_name("INPUT")
                 .help("The input file. Either a ROM or source file")
-                .required(true)
+                .required_unless("dap")
                 .index(1),
         )
         .arg(
@@ -18,20 +42,267 @@ pub fn main() {
                 .help("Compile a ROM from an assembly source")
                 .short("c"),
         )
+        .arg(
+            Arg::with_name("output")
+                .help("Where to write the compiled ROM (defaults to INPUT with a .ch8 extension)")
+                .requires("compile")
+                .short("o")
+                .long("output")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("decompile")
                 .conflicts_with("compile")
                 .help("Decompile a ROM to assembly source")
                 .short("d"),
         )
+        .arg(
+            Arg::with_name("round-trip")
+                .help("Decompile to source that reassembles byte-for-byte to the ROM")
+                .requires("decompile")
+                .short("r")
+                .long("round-trip"),
+        )
+        .arg(
+            Arg::with_name("linear")
+                .help("Decompile every word in order instead of following control flow")
+                .requires("decompile")
+                .short("l")
+                .long("linear"),
+        )
+        .arg(
+            Arg::with_name("listing")
+                .help("Decompile to a listing with the address and raw bytes of every line")
+                .requires("decompile")
+                .conflicts_with("round-trip")
+                .long("listing"),
+        )
+        .arg(
+            Arg::with_name("graph")
+                .help("Export the control flow graph of a ROM in Graphviz DOT format")
+                .conflicts_with_all(&["compile", "decompile"])
+                .short("g")
+                .long("graph"),
+        )
+        .arg(
+            Arg::with_name("on-fault")
+                .help("What to do when a ROM overflows the stack or reaches past the end of memory (defaults to error)")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("on-fault")
+                .takes_value(true)
+                .possible_values(&["error", "trap", "wrap"]),
+        )
+        .arg(
+            Arg::with_name("ipf")
+                .help("How many instructions to run every 60th of a second (defaults to 10)")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("ipf")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("quirks")
+                .help("Which interpreter's quirks to emulate (defaults to cowgod)")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("quirks")
+                .takes_value(true)
+                .possible_values(&["cowgod", "vip", "chip48", "schip", "xochip"]),
+        )
+        .arg(
+            Arg::with_name("frequency")
+                .help("The pitch of the beep, in Hz (defaults to 440)")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("frequency")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("volume")
+                .help("How loud the beep is, from 0 to 1 (defaults to 0.25)")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("volume")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("waveform")
+                .help("The shape of the beep (defaults to square)")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("waveform")
+                .takes_value(true)
+                .possible_values(&["square", "triangle", "sawtooth", "sine"]),
+        )
+        .arg(
+            Arg::with_name("mute")
+                .help("Start with the sound off. M toggles it while running")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("mute"),
+        )
+        .arg(
+            Arg::with_name("rewind-depth")
+                .help("How many instructions to keep for rewinding with Backspace and stepping back in the debuggers, or 0 for none (defaults to 36000)")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("rewind-depth")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("rewind-budget")
+                .help("The most memory that may go to rewinding, in MiB (defaults to 16)")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("rewind-budget")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("debug")
+                .help("Run the ROM under a debugger, taking commands from the terminal")
+                .conflicts_with_all(&["compile", "decompile", "graph"])
+                .long("debug"),
+        )
+        .arg(
+            Arg::with_name("gdb")
+                .help("Wait for a GDB client to connect on this port of localhost, and run the ROM under it")
+                .conflicts_with_all(&["compile", "decompile", "graph", "debug"])
+                .long("gdb")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("dap")
+                .help("Serve the Debug Adapter Protocol on stdin and stdout, so that an editor can debug assembly source. The editor launches the source file")
+                .conflicts_with_all(&["INPUT", "compile", "decompile", "graph", "debug", "gdb"])
+                .long("dap"),
+        )
         .get_matches();
-    let input = matches.value_of("INPUT").unwrap().to_string();
+    let input = matches.value_of("INPUT").unwrap_or_default().to_string();
     if matches.is_present("compile") {
-        println!("Compile flag");
-        // TODO: call compilation method
+        let output = match matches.value_of("output") {
+            Some(output) => output.to_string(),
+            None => Path::new(&input)
+                .with_extension("ch8")
+                .to_string_lossy()
+                .into_owned(),
+        };
+        if output == input {
+            eprintln!("Refusing to overwrite the input file {}", input);
+            process::exit(1);
+        }
+        match assembler::assemble_file(input.clone()) {
+            Ok(rom) => {
+                if let Err(e) = fs::write(&output, rom) {
+                    eprintln!("Cannot write ROM to {}: {}", output, e);
+                    process::exit(1);
+                }
+            }
+            Err(e) => {
+                eprintln!("{}", e);
+                process::exit(1);
+            }
+        }
     } else if matches.is_present("decompile") {
-        println!("{}", decompiler::decompile_rom(input));
+        let options = decompiler::DecompileOptions {
+            round_trip: matches.is_present("round-trip"),
+            linear_sweep: matches.is_present("linear"),
+            listing: matches.is_present("listing"),
+        };
+        print_or_exit(decompiler::decompile_rom(input, &options));
+    } else if matches.is_present("graph") {
+        print_or_exit(graph::graph_rom(input));
+    } else {
+        run(input, &matches);
+    }
+}
+
+// print a decompilation or graph to stdout, or its error to stderr
+fn print_or_exit<E: std::fmt::Display>(output: Result<String, E>) {
+    match output {
+        Ok(output) => println!("{}", output),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn run(input: String, matches: &ArgMatches) {
+    let mut faults = FaultPolicy::default();
+    if matches.is_present("on-fault") {
+        faults = FaultPolicy::all(
+            value_t!(matches, "on-fault", FaultAction).unwrap_or_else(|e| e.exit()),
+        );
+    }
+    let mut tone = ToneSettings {
+        muted: matches.is_present("mute"),
+        ..ToneSettings::default()
+    };
+    if matches.is_present("frequency") {
+        tone.frequency = value_t!(matches, "frequency", f32).unwrap_or_else(|e| e.exit());
+    }
+    if matches.is_present("volume") {
+        tone.volume = value_t!(matches, "volume", f32).unwrap_or_else(|e| e.exit());
+    }
+    if matches.is_present("waveform") {
+        tone.waveform = value_t!(matches, "waveform", Waveform).unwrap_or_else(|e| e.exit());
+    }
+    // under DAP, the editor says which program to load
+    let chip8 = if matches.is_present("dap") {
+        Ok(emulator::Chip8::new())
     } else {
-        emulator::Chip8::with_rom(input).run();
+        emulator::Chip8::with_rom(input)
+    };
+    let result = chip8.and_then(|mut chip8| {
+        if matches.is_present("ipf") {
+            chip8.set_instructions_per_frame(
+                value_t!(matches, "ipf", u32).unwrap_or_else(|e| e.exit()),
+            );
+        }
+        if matches.is_present("quirks") {
+            chip8.set_quirks(value_t!(matches, "quirks", Quirks).unwrap_or_else(|e| e.exit()));
+        }
+        chip8.set_fault_policy(faults);
+        let mut depth = REWIND_DEPTH;
+        if matches.is_present("rewind-depth") {
+            depth = value_t!(matches, "rewind-depth", usize).unwrap_or_else(|e| e.exit());
+        }
+        let mut budget = REWIND_BUDGET;
+        if matches.is_present("rewind-budget") {
+            budget = value_t!(matches, "rewind-budget", usize).unwrap_or_else(|e| e.exit());
+        }
+        chip8.set_rewind(depth, budget.saturating_mul(1 << 20));
+        let mut frontend = SdlFrontend::with_tone(tone);
+        if matches.is_present("debug") {
+            let stdin = io::stdin();
+            Debugger::new()
+                .repl(&mut chip8, &mut frontend, stdin.lock(), io::stdout())
+                .map_err(emulator::Chip8Error::from)
+        } else if matches.is_present("gdb") {
+            let port = value_t!(matches, "gdb", u16).unwrap_or_else(|e| e.exit());
+            serve_gdb(&mut chip8, &mut frontend, port).map_err(emulator::Chip8Error::from)
+        } else if matches.is_present("dap") {
+            let messages = dap::spawn_reader(BufReader::new(io::stdin()));
+            DapServer::new(messages, io::stdout())
+                .serve(&mut chip8, &mut frontend)
+                .map_err(emulator::Chip8Error::from)
+        } else {
+            let result = chip8.run_with(&mut frontend);
+            if let Some(trap) = chip8.trap() {
+                eprintln!("Trapped: {}", trap);
+            }
+            result
+        }
+    });
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
     }
 }
+
+#[cfg(feature = "sdl")]
+fn serve_gdb(chip8: &mut emulator::Chip8, frontend: &mut SdlFrontend, port: u16) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!("Waiting for GDB on 127.0.0.1:{}", port);
+    let (stream, _) = listener.accept()?;
+    GdbStub::new(stream).serve(chip8, frontend)
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run(_input: String, _matches: &ArgMatches) {
+    eprintln!("Running ROMs needs the `sdl` feature, which this build was compiled without");
+    process::exit(1);
+}